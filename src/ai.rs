@@ -0,0 +1,321 @@
+use board::*;
+use game::{Action, Game};
+use json::Json;
+use path::{reachable_placements, Movement};
+use piece::Piece;
+use rules::FRAMES_PER_SECOND;
+use std::fs::File;
+use std::io::Read;
+use util::*;
+
+/// The weights given to each feature of a board when judging how good it is to leave it that way. Features
+/// which are bad for the player have negative weights.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Weights {
+    /// The sum of the heights of every column.
+    pub aggregate_height: f64,
+    /// The number of lines cleared getting to the board.
+    pub lines: f64,
+    /// The number of empty cells with a block somewhere above them.
+    pub holes: f64,
+    /// The sum of the differences in height between neighbouring columns.
+    pub bumpiness: f64,
+    /// The depth of the wells: empty cells with blocks or walls either side. Deeper wells count for more, as
+    /// each cell of a well counts its depth so far.
+    pub wells: f64,
+}
+
+/// Weights which play a steady game, after those found by Yiyuan Lee for his Tetris AI, with a small penalty
+/// for wells.
+pub const DEFAULT_WEIGHTS: Weights = Weights{
+    aggregate_height: -0.510066,
+    lines: 0.760666,
+    holes: -0.35663,
+    bumpiness: -0.184483,
+    wells: -0.1,
+};
+
+impl Weights {
+    /// Reads weights from a JSON object with a member for each feature, as written by `to_json`.
+    pub fn from_json(json: &Json) -> Result<Weights, String> {
+        let weight = |name: &str| {
+            json.get(name).and_then(Json::as_f64).ok_or_else(|| format!("Missing weight for '{}'", name))
+        };
+        Ok(Weights{
+            aggregate_height: weight("aggregate_height")?,
+            lines: weight("lines")?,
+            holes: weight("holes")?,
+            bumpiness: weight("bumpiness")?,
+            wells: weight("wells")?,
+        })
+    }
+
+    /// Reads weights from a JSON file.
+    pub fn load(path: &str) -> Result<Weights, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|err| format!("Couldn't read weights from {}: {}", path, err))?;
+        let json = Json::parse(&text).map_err(|err| format!("Couldn't read weights from {}: {}", path, err))?;
+        Weights::from_json(&json)
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("aggregate_height", self.aggregate_height.into()),
+            ("lines", self.lines.into()),
+            ("holes", self.holes.into()),
+            ("bumpiness", self.bumpiness.into()),
+            ("wells", self.wells.into()),
+        ])
+    }
+
+    /// Scores a board reached by clearing `lines` lines. Higher scores are better.
+    pub fn evaluate(&self, board: &Board, lines: u32) -> f64 {
+        let mut heights = [0i32; BOARD_WIDTH as usize];
+        let mut holes = 0;
+        for (col, height) in heights.iter_mut().enumerate() {
+            let mut filled = false;
+            for row in 0..(BOARD_HEIGHT as usize) {
+                if board.cells[row][col].is_some() {
+                    if !filled {
+                        *height = (BOARD_HEIGHT as usize - row) as i32;
+                        filled = true;
+                    }
+                } else if filled {
+                    holes += 1;
+                }
+            }
+        }
+
+        let aggregate_height: i32 = heights.iter().sum();
+        let bumpiness: i32 = heights.windows(2).map(|pair| (pair[0] - pair[1]).abs()).sum();
+
+        let mut wells = 0;
+        for col in 0..(BOARD_WIDTH as usize) {
+            let mut depth = 0;
+            for row in 0..(BOARD_HEIGHT as usize) {
+                let blocked = |c: i32| c < 0 || c >= BOARD_WIDTH as i32 || board.cells[row][c as usize].is_some();
+                if board.cells[row][col].is_none() && blocked(col as i32 - 1) && blocked(col as i32 + 1) {
+                    depth += 1;
+                    wells += depth;
+                } else {
+                    depth = 0;
+                }
+            }
+        }
+
+        self.aggregate_height * aggregate_height as f64 + self.lines * lines as f64 + self.holes * holes as f64 +
+            self.bumpiness * bumpiness as f64 + self.wells * wells as f64
+    }
+}
+
+/// A place the current piece can be put: the piece turned to its final rotation, and the position it locks
+/// in. If `hold` is set, the piece placed is the one swapped in by using hold first.
+#[derive(Clone)]
+pub struct Placement {
+    pub piece: Piece,
+    pub position: Point,
+    pub hold: bool,
+}
+
+impl Placement {
+    /// Returns the board left by locking the piece here, and the number of lines that clears.
+    pub fn result(&self, board: &Board) -> (Board, u32) {
+        let mut board = board.clone();
+        board.lock_piece(&self.piece, self.position, 0);
+        let rows = board.full_rows();
+        board.remove_rows(&rows);
+        (board, rows.len() as u32)
+    }
+
+    /// Returns the cells of the board the piece covers, in reading order.
+    pub fn cells(&self) -> Vec<Point> {
+        let mut cells = Vec::new();
+        let position = self.position;
+        self.piece.each_point(&mut |row, col| cells.push(Point{ x: position.x + col, y: position.y + row }));
+        cells
+    }
+}
+
+/// Finds every placement of a piece at `origin` which can be reached by turning it on the spot, sliding it to
+/// one side and dropping it. Placements which cover the same cells are only returned once.
+pub fn placements(board: &Board, piece: &Piece, origin: Point) -> Vec<Placement> {
+    let mut found: Vec<Placement> = Vec::new();
+    let mut rotated = piece.clone();
+    for _ in 0..4 {
+        if !board.collision_test(&rotated, origin) {
+            for &step in &[-rotated.scale(), rotated.scale()] {
+                let mut x = origin.x;
+                while !board.collision_test(&rotated, Point{ x, y: origin.y }) {
+                    let mut position = Point{ x, y: origin.y };
+                    while !board.collision_test(&rotated, Point{ y: position.y + rotated.scale(), ..position }) {
+                        position.y += rotated.scale();
+                    }
+
+                    let placement = Placement{ piece: rotated.clone(), position, hold: false };
+                    let cells = placement.cells();
+                    if !found.iter().any(|p| p.cells() == cells) {
+                        found.push(placement);
+                    }
+                    x += step;
+                }
+            }
+        }
+        rotated.rotate(Direction::Right);
+    }
+    found
+}
+
+/// Chooses the best placement for `piece` at `origin` out of all those it can reach, looking ahead to where
+/// the `next` piece could go after it. The next piece is only tried with straight drops, which keeps the search
+/// quick.
+pub fn best_placement(board: &Board, piece: &Piece, origin: Point, next: Option<&Piece>, movement: Movement,
+                      weights: &Weights) -> Option<Placement> {
+    ranked_placements(board, piece, origin, next, movement, weights).into_iter().next()
+}
+
+/// Returns every placement `piece` at `origin` can reach, judged as `best_placement` does, from best to worst.
+pub fn ranked_placements(board: &Board, piece: &Piece, origin: Point, next: Option<&Piece>, movement: Movement,
+                         weights: &Weights) -> Vec<Placement> {
+    let mut ranked: Vec<(f64, Placement)> = reachable_placements(board, piece, origin, movement).into_iter()
+        .map(|placement| {
+            let (after, lines) = placement.result(board);
+            let score = match next {
+                Some(next) => {
+                    placements(&after, next, Game::spawn_position(next)).iter()
+                        .map(|p| {
+                            let (last, more_lines) = p.result(&after);
+                            weights.evaluate(&last, lines + more_lines)
+                        })
+                        .fold(None, |best: Option<f64>, score| Some(best.map_or(score, |b| b.max(score))))
+                        .unwrap_or(weights.evaluate(&after, lines))
+                },
+                None => weights.evaluate(&after, lines),
+            };
+            (score, placement)
+        })
+        .collect();
+    // A stable sort keeps the first of equally good placements first, as the search has always chosen.
+    ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(::std::cmp::Ordering::Equal));
+    ranked.into_iter().map(|(_, placement)| placement).collect()
+}
+
+/// Chooses where the current piece of a game should go, for an `Autoplayer` to carry out.
+pub trait Planner {
+    /// Chooses a placement for the current piece. Returns `None` if the planner needs more time, in which case
+    /// it is asked again on a later frame.
+    fn plan(&mut self, game: &Game) -> Option<Placement>;
+}
+
+/// Plans placements with the AI, judging boards with a set of weights.
+pub struct HeuristicPlanner {
+    weights: Weights,
+}
+
+impl HeuristicPlanner {
+    pub fn new(weights: Weights) -> HeuristicPlanner {
+        HeuristicPlanner{ weights }
+    }
+}
+
+impl Planner for HeuristicPlanner {
+    fn plan(&mut self, game: &Game) -> Option<Placement> {
+        let (piece, position) = game.current_piece()?;
+        let next = game.next_piece();
+        best_placement(game.board(), piece, position, Some(&next), game.movement(), &self.weights).or_else(|| {
+            Some(Placement{ piece: piece.clone(), position: game.find_dropped_position(), hold: false })
+        })
+    }
+}
+
+/// Plays a game by choosing placements with a planner and carrying them out one action at a time, along the
+/// shortest path to each.
+pub struct Autoplayer {
+    planner: Box<dyn Planner>,
+    /// The number of frames between actions.
+    interval: u32,
+    counter: u32,
+    /// The placement chosen for the current piece, the number of pieces locked before it, whether the piece
+    /// has been swapped with hold to reach it, and the rotation and position the piece was in before the last
+    /// action taken towards it.
+    target: Option<Placement>,
+    pieces: u32,
+    held: bool,
+    last: Option<(usize, Point)>,
+}
+
+impl Autoplayer {
+    /// Creates an autoplayer which carries out the placements chosen by `planner`, taking `speed` actions a
+    /// second.
+    pub fn new(planner: Box<dyn Planner>, speed: u32) -> Autoplayer {
+        Autoplayer{
+            planner,
+            interval: ::std::cmp::max(FRAMES_PER_SECOND / ::std::cmp::max(speed, 1), 1),
+            counter: 0,
+            target: None,
+            pieces: 0,
+            held: false,
+            last: None,
+        }
+    }
+
+    /// Runs a frame of the game, returning the action to perform, if it's time for one.
+    pub fn update(&mut self, game: &Game) -> Option<Action> {
+        let (piece, position) = match game.current_piece() {
+            Some(current) => current,
+            None => {
+                self.target = None;
+                return None;
+            },
+        };
+
+        // The piece may have been replaced without any being locked, by undo or by the player using hold.
+        let stale = self.target.as_ref().is_none_or(|target| {
+            self.pieces != game.pieces() || (!target.hold && target.piece.name() != piece.name())
+        });
+        if stale {
+            self.target = self.planner.plan(game);
+            self.target.as_ref()?;
+            self.pieces = game.pieces();
+            self.held = false;
+            self.last = None;
+            self.counter = 0;
+        }
+
+        self.counter += 1;
+        if self.counter < self.interval {
+            return None;
+        }
+        self.counter = 0;
+
+        let target = match self.target {
+            Some(ref target) => target,
+            None => return Some(self.drop_action(game)),
+        };
+        if target.hold && target.piece.name() != piece.name() {
+            if self.held {
+                return Some(self.drop_action(game));
+            }
+            self.held = true;
+            return Some(Action::Hold);
+        }
+
+        // Give up and drop the piece where it is if the last action didn't get it anywhere.
+        let state = (piece.rotation(), position);
+        if self.last == Some(state) {
+            return Some(self.drop_action(game));
+        }
+        self.last = Some(state);
+
+        // The path is found afresh for each action, as gravity may have moved the piece since the last.
+        match game.find_path(target) {
+            Some(path) => path.first().cloned().or_else(|| Some(self.drop_action(game))),
+            None => Some(self.drop_action(game)),
+        }
+    }
+
+    /// Returns the action which drops the piece: a hard drop, or a soft drop if hard drops aren't allowed.
+    fn drop_action(&self, game: &Game) -> Action {
+        if game.rules().hard_drop { Action::HardDrop } else { Action::SoftDrop }
+    }
+}
@@ -0,0 +1,206 @@
+extern crate tetris;
+
+use tetris::ai::{Weights, DEFAULT_WEIGHTS};
+use tetris::json::Json;
+use tetris::piece::{PieceSet, Randomizer};
+use tetris::rules::{Mode, Rules};
+use tetris::sim::{self, Summary};
+use tetris::tune::{Population, Settings};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+
+fn usage() -> ! {
+    println!("Usage: tetris-sim [--seeds <first>..<last>] [--weights <file>] [<game options>]");
+    println!("       tetris-sim tune [--seeds <first>..<last>] [--population <n>] [--elite <n>]");
+    println!("                       [--generations <n>] [--threads <n>] [--checkpoint <file>] [--output <file>]");
+    println!("                       [<game options>]");
+    println!();
+    println!("Plays games with the AI without a display, one for each seed, and prints statistics about them");
+    println!("as JSON. With `tune`, searches for the AI weights which clear the most lines instead, printing");
+    println!("the progress of each generation as a line of JSON.");
+    println!();
+    println!("Options:");
+    println!("    --seeds <first>..<last>  Seeds of the games to play, including the first but not the last");
+    println!("                             (default 0..100, or 0..10 for each candidate when tuning)");
+    println!("    --weights <file>         JSON file of weights for the AI (default: the built-in weights)");
+    println!("    --population <n>         Number of candidate weights tried in each generation (default 50)");
+    println!("    --elite <n>              Number of the best candidates the next generation is drawn from");
+    println!("                             (default 10)");
+    println!("    --generations <n>        Number of generations to run in all (default 20)");
+    println!("    --threads <n>            Number of games to play at once (default: one for each CPU)");
+    println!("    --checkpoint <file>      File to save the search to after each generation, and to resume it");
+    println!("                             from if it exists (default tune-checkpoint.json)");
+    println!("    --output <file>          File to write the best weights found to (default weights.json)");
+    println!();
+    println!("Game options:");
+    println!("    --mode <mode>            Game mode: marathon (default), nes, master or zen");
+    println!("    --pieces <set>           Piece set to play with, by name or as the path of a piece set file");
+    println!("    --randomizer <name>      How pieces are dealt: bag, nes or history (default: the mode's)");
+    println!("    --max-pieces <n>         Number of pieces after which a game is stopped (default 1000, or 500");
+    println!("                             when tuning)");
+    std::process::exit(1);
+}
+
+/// Parses a range of seeds written as `<first>..<last>`.
+fn parse_seeds(text: &str) -> Option<(u64, u64)> {
+    let mut parts = text.splitn(2, "..");
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(first)), Some(Ok(last))) if first <= last => Some((first, last)),
+        _ => None,
+    }
+}
+
+fn exit_with_error(err: String) -> ! {
+    println!("{}", err);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    let tune = args.peek().is_some_and(|arg| arg == "tune");
+    if tune {
+        args.next();
+    }
+
+    let mut seeds = if tune { (0, 10) } else { (0, 100) };
+    let mut mode_name = "marathon".to_string();
+    let mut piece_set = None;
+    let mut randomizer = None;
+    let mut weights = DEFAULT_WEIGHTS;
+    let mut max_pieces = if tune { 500 } else { 1000 };
+    let mut population = 50;
+    let mut elite = 10;
+    let mut generations = 20;
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut checkpoint = "tune-checkpoint.json".to_string();
+    let mut output = "weights.json".to_string();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seeds" => {
+                let range = args.next().unwrap_or_else(|| usage());
+                seeds = parse_seeds(&range).unwrap_or_else(|| usage());
+            },
+            "--mode" => mode_name = args.next().unwrap_or_else(|| usage()),
+            "--pieces" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                piece_set = Some(PieceSet::find(&name).unwrap_or_else(|err| exit_with_error(err)));
+            },
+            "--randomizer" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                randomizer = Some(Randomizer::from_name(&name).unwrap_or_else(|| usage()));
+            },
+            "--weights" if !tune => {
+                let path = args.next().unwrap_or_else(|| usage());
+                weights = Weights::load(&path).unwrap_or_else(|err| exit_with_error(err));
+            },
+            "--max-pieces" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                max_pieces = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--population" if tune => {
+                let n = args.next().unwrap_or_else(|| usage());
+                population = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--elite" if tune => {
+                let n = args.next().unwrap_or_else(|| usage());
+                elite = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--generations" if tune => {
+                let n = args.next().unwrap_or_else(|| usage());
+                generations = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--threads" if tune => {
+                let n = args.next().unwrap_or_else(|| usage());
+                threads = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--checkpoint" if tune => checkpoint = args.next().unwrap_or_else(|| usage()),
+            "--output" if tune => output = args.next().unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+
+    if population < 1 {
+        exit_with_error("--population must be at least 1".to_string());
+    }
+    if elite < 1 || elite > population {
+        exit_with_error(format!("--elite must be between 1 and the population of {}", population));
+    }
+
+    let mode = Mode::from_name(&mode_name).unwrap_or_else(|| usage());
+    let mut rules = mode.rules();
+    if let Some(piece_set) = piece_set {
+        rules.piece_set = piece_set;
+    }
+    if let Some(randomizer) = randomizer {
+        rules.randomizer = randomizer;
+    }
+
+    if tune {
+        let settings = Settings{
+            rules,
+            seeds,
+            max_pieces,
+            population,
+            elite,
+            threads,
+        };
+        run_tuner(&settings, generations, &checkpoint, &output);
+    } else {
+        run_games(rules, seeds, weights, max_pieces, &mode_name);
+    }
+}
+
+/// Plays a game for each seed and prints statistics about them all.
+fn run_games(rules: Rules, seeds: (u64, u64), weights: Weights, max_pieces: u32, mode_name: &str) {
+    let mut summary = Summary::default();
+    for seed in seeds.0..seeds.1 {
+        summary.add(&sim::play(rules.clone(), seed, weights, max_pieces));
+    }
+
+    let randomizer_name = match rules.randomizer {
+        Randomizer::Bag => "bag",
+        Randomizer::Nes => "nes",
+        Randomizer::History => "history",
+    };
+    let mut output = Json::object(vec![
+        ("mode", mode_name.into()),
+        ("randomizer", randomizer_name.into()),
+        ("seeds", Json::Array(vec![(seeds.0 as f64).into(), (seeds.1 as f64).into()])),
+        ("max_pieces", max_pieces.into()),
+        ("weights", weights.to_json()),
+    ]);
+    if let (&mut Json::Object(ref mut members), Json::Object(results)) = (&mut output, summary.to_json()) {
+        members.extend(results);
+    }
+    println!("{}", output);
+}
+
+/// Runs the tuner until it has been through `generations` generations, resuming from the checkpoint if there
+/// is one, and writes the best weights found to `output` after each generation.
+fn run_tuner(settings: &Settings, generations: u32, checkpoint: &str, output: &str) {
+    let mut population = if Path::new(checkpoint).exists() {
+        Population::load(checkpoint).unwrap_or_else(|err| exit_with_error(err))
+    } else {
+        Population::new()
+    };
+
+    while population.generation < generations {
+        let results = population.step(settings);
+        population.save(checkpoint).unwrap_or_else(|err| exit_with_error(err));
+        let best = population.best.to_json();
+        File::create(output).and_then(|mut file| writeln!(file, "{}", best))
+            .unwrap_or_else(|err| exit_with_error(format!("Couldn't write {}: {}", output, err)));
+
+        let mean = results.iter().map(|&(_, lines)| lines).sum::<f64>() / results.len() as f64;
+        println!("{}", Json::object(vec![
+            ("generation", population.generation.into()),
+            ("generation_best_lines", results[0].1.into()),
+            ("generation_mean_lines", mean.into()),
+            ("best_lines", population.best_lines.into()),
+            ("best", best),
+        ]));
+    }
+}
@@ -0,0 +1,221 @@
+use display::Display;
+use piece::Piece;
+use rules::{StackVisibility, FRAMES_PER_SECOND};
+use util::*;
+
+pub const BOARD_WIDTH: u32 = 10;
+pub const BOARD_HEIGHT: u32 = 40;
+
+/// Rows at the top of the board which make up the vanish zone. Pieces spawn here and garbage can push the
+/// stack up into it, but nothing locked in these rows is ever drawn.
+pub const HIDDEN_ROWS: u32 = 20;
+
+/// Number of vanish zone rows directly above the visible field in which the active piece is still drawn, so
+/// that a freshly spawned piece is partially visible.
+pub const SPAWN_ROWS: u32 = 2;
+
+#[derive(Clone)]
+pub struct Board {
+    pub cells: [[Option<Color>; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+    /// The frame on which each cell was filled, used to fade the stack out.
+    locked_at: [[u32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Board {
+    pub fn new() -> Board {
+        Board{
+            cells: [[None; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+            locked_at: [[0; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+        }
+    }
+
+    /// Converts a board row into a display row. Only rows for which this returns a value are drawn; the
+    /// visible field starts `SPAWN_ROWS` lines down the display.
+    pub fn screen_row(row: i32) -> Option<u32> {
+        let top = (HIDDEN_ROWS - SPAWN_ROWS) as i32;
+        if row < top || row >= BOARD_HEIGHT as i32 {
+            None
+        } else {
+            Some((row - top) as u32)
+        }
+    }
+
+    /// Draws the board. Only as much of the stack as `visibility` allows is drawn, `frame` frames into the game.
+    pub fn render(&self, display: &mut Display, visibility: StackVisibility, frame: u32) {
+        let floor = BOARD_HEIGHT - HIDDEN_ROWS + SPAWN_ROWS;
+        let right = BOARD_WIDTH * 2 + 1;
+        for y in SPAWN_ROWS..floor {
+            display.set_text("│", 0, y, Color::Red, Color::Black);
+            display.set_text("│", right, y, Color::Red, Color::Black);
+        }
+        for x in 1..right {
+            display.set_text("─", x, floor, Color::Red, Color::Black);
+        }
+        display.set_text("└", 0, floor, Color::Red, Color::Black);
+        display.set_text("┘", right, floor, Color::Red, Color::Black);
+        for row in HIDDEN_ROWS..BOARD_HEIGHT {
+            for col in 0..BOARD_WIDTH {
+                if let Some(color) = self.visible_color(row as usize, col as usize, visibility, frame) {
+                    let c = 1 + (col * 2);
+                    let y = Board::screen_row(row as i32).unwrap();
+                    display.set_text(" ", c, y, color, color);
+                    display.set_text(" ", c + 1, y, color, color);
+                }
+            }
+        }
+    }
+
+    /// Returns the color a cell is drawn in, if it can be seen. Fading blocks turn gray for the last second
+    /// before they disappear.
+    fn visible_color(&self, row: usize, col: usize, visibility: StackVisibility, frame: u32) -> Option<Color> {
+        let color = self.cells[row][col];
+        match visibility {
+            StackVisibility::Visible => color,
+            StackVisibility::Invisible => None,
+            StackVisibility::Fading(frames) => {
+                let age = frame.saturating_sub(self.locked_at[row][col]);
+                if age >= frames {
+                    None
+                } else if age + FRAMES_PER_SECOND >= frames {
+                    color.map(|_| Color::Gray)
+                } else {
+                    color
+                }
+            },
+        }
+    }
+
+    /// Fills the board's cells under a piece, recording that they were filled on frame `frame`.
+    pub fn lock_piece(&mut self, piece: &Piece, origin: Point, frame: u32) {
+        piece.each_point(&mut |row, col| {
+            let x = origin.x + (col as i32);
+            let y = origin.y + (row as i32);
+            self.cells[y as usize][x as usize] = Some(piece.color());
+            self.locked_at[y as usize][x as usize] = frame;
+        });
+    }
+
+    pub fn collision_test(&self, piece: &Piece, origin: Point) -> bool {
+        self.first_collision(piece, origin).is_some()
+    }
+
+    /// Returns the position within the piece's box of the first of its blocks, in reading order, which
+    /// overlaps the stack or lies outside the board.
+    pub fn first_collision(&self, piece: &Piece, origin: Point) -> Option<Point> {
+        let mut found = None;
+        piece.each_point(&mut |row, col| {
+            if found.is_none() {
+                let x = origin.x + col;
+                let y = origin.y + row;
+                if x < 0 || x >= (BOARD_WIDTH as i32) || y < 0 || y >= (BOARD_HEIGHT as i32) ||
+                    self.cells[y as usize][x as usize] != None {
+                  found = Some(Point{ x: col, y: row });
+                }
+            }
+        });
+
+        found
+    }
+
+    /// Rotates a piece at `origin` in the given direction, trying each of its kick offsets in turn. Returns the
+    /// rotated piece, its new position and the index of the kick used, or `None` if it can't be rotated.
+    pub fn rotate_piece(&self, piece: &Piece, origin: Point, direction: Direction)
+                        -> Option<(Piece, Point, usize)> {
+        let mut rotated = piece.clone();
+        rotated.rotate(direction);
+
+        if piece.center_column_rule() {
+            let scale = rotated.scale();
+            let center = (rotated.size() as i32 / scale) / 2;
+            match self.first_collision(&rotated, origin) {
+                Some(Point{ x, .. }) if x / scale == center => return None,
+                _ => (),
+            }
+        }
+
+        for (i, offset) in piece.kicks(direction).iter().enumerate() {
+            let position = Point{ x: origin.x + offset.x, y: origin.y + offset.y };
+            if !self.collision_test(&rotated, position) {
+                return Some((rotated, position, i));
+            }
+        }
+        None
+    }
+
+    /// Returns the indices of the rows which are completely filled, from top to bottom.
+    pub fn full_rows(&self) -> Vec<usize> {
+        (0..self.cells.len()).filter(|&row| self.cells[row].iter().all(|c| c.is_some())).collect()
+    }
+
+    /// Removes the given rows from the board, shifting the rows above them down to take their place. `rows`
+    /// must be sorted from top to bottom, as returned by `full_rows`.
+    pub fn remove_rows(&mut self, rows: &[usize]) {
+        for &removed in rows {
+            for row in (1..(removed + 1)).rev() {
+                self.cells[row] = self.cells[row - 1];
+                self.locked_at[row] = self.locked_at[row - 1];
+            }
+            self.cells[0] = [None; BOARD_WIDTH as usize];
+        }
+    }
+
+    /// Draws the animation of `rows` being cleared, `elapsed` frames into a clear lasting `frames` frames.
+    /// The rows flash for the first half of the clear, then are wiped away from the middle outwards. Only the
+    /// blocks that can be seen under `visibility` on frame `frame` are drawn.
+    pub fn render_clearing_rows(&self, display: &mut Display, rows: &[usize], visibility: StackVisibility,
+                                elapsed: u32, frames: u32, frame: u32) {
+        let flash = frames / 2;
+        let half_width = BOARD_WIDTH / 2;
+        let wiped = if elapsed < flash {
+            0
+        } else {
+            ::std::cmp::min((elapsed - flash + 1) * half_width / ::std::cmp::max(frames - flash, 1), half_width)
+        };
+
+        for &row in rows {
+            let y = match Board::screen_row(row as i32) {
+                Some(y) => y,
+                None => continue,
+            };
+            for col in 0..BOARD_WIDTH {
+                let color = match self.visible_color(row, col as usize, visibility, frame) {
+                    _ if col + wiped >= half_width && col < half_width + wiped => Color::Black,
+                    Some(_) if elapsed < flash && (elapsed / 4).is_multiple_of(2) => Color::White,
+                    Some(color) => color,
+                    None => Color::Black,
+                };
+                let x = 1 + (col * 2);
+                display.set_text(" ", x, y, color, color);
+                display.set_text(" ", x + 1, y, color, color);
+            }
+        }
+    }
+
+    /// Pushes the stack up by `lines` rows and fills the bottom with garbage locked on `frame`, leaving the
+    /// `width` cells from column `hole` empty. Returns false if any blocks were pushed out of the top of the
+    /// vanish zone.
+    pub fn insert_garbage(&mut self, lines: u32, hole: u32, width: u32, frame: u32) -> bool {
+        let lines = ::std::cmp::min(lines, BOARD_HEIGHT) as usize;
+        let topped_out = self.cells[..lines].iter().any(|row| row.iter().any(|c| c.is_some()));
+
+        for row in 0..(self.cells.len() - lines) {
+            self.cells[row] = self.cells[row + lines];
+            self.locked_at[row] = self.locked_at[row + lines];
+        }
+        for row in (self.cells.len() - lines)..self.cells.len() {
+            self.cells[row] = [Some(Color::Gray); BOARD_WIDTH as usize];
+            for col in hole..::std::cmp::min(hole + width, BOARD_WIDTH) {
+                self.cells[row][col as usize] = None;
+            }
+            self.locked_at[row] = [frame; BOARD_WIDTH as usize];
+        }
+
+        !topped_out
+    }
+}
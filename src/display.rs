@@ -1,113 +1,165 @@
 use util::Color;
 use std::io::{self, Write};
+use unicode_width::UnicodeWidthChar;
 
 const ESC: &'static str = "\x1b";
 
-#[derive(Debug)]
+/// Fills the second column of a wide character, which the character itself is drawn over.
+const CONTINUATION: char = '\0';
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 struct Pixel {
     c: char,
     fg_color: Color,
     bg_color: Color,
 }
 
+const BLANK: Pixel = Pixel{ c: ' ', fg_color: Color::Black, bg_color: Color::Black };
+
+/// A buffer of text which is drawn to the terminal. Text is drawn into the back buffer, and `render` compares it
+/// with the front buffer, which holds what the terminal is showing, to write only the cells that have changed.
 pub struct Display {
     buffer: Vec<Vec<Pixel>>,
+    /// What the terminal is showing, or `None` if that isn't known and the whole screen must be redrawn.
+    front: Option<Vec<Vec<Pixel>>>,
+    /// The colors the terminal is set to draw in, if known.
+    colors: Option<(Color, Color)>,
+    /// The column that text is drawn relative to.
+    origin: u32,
 }
 
 impl Display {
     pub fn new(width: u32, height: u32) -> Display {
-        let mut rows = Vec::with_capacity(height as usize);
-        for _ in 0..height {
-            let mut row = Vec::with_capacity(width as usize);
-            for _ in 0..width {
-                row.push(Pixel{ c: ' ', fg_color: Color::Black, bg_color: Color::Black });
-            }
-            rows.push(row);
-        }
-
         Display {
-            buffer: rows
+            buffer: vec![vec![BLANK; width as usize]; height as usize],
+            front: None,
+            colors: None,
+            origin: 0,
         }
     }
 
+    /// Brings the terminal up to date with the buffer, writing to standard output.
     pub fn render(&mut self) {
-        self.clear_screen();
-        self.set_cursor_pos(0, 0);
+        assert!(self.render_to(&mut io::stdout()).is_ok());
+    }
 
-        let mut writer = io::stdout();
-        let mut fg_color = Color::Black;
-        let mut bg_color = Color::Black;
-
-        let mut y = 0;
-
-        for row in &self.buffer {
-            for pixel in row {
-                if pixel.fg_color != fg_color {
-                    fg_color = pixel.fg_color;
-                    self.set_fg_color(pixel.fg_color);
-                 }
-                if pixel.bg_color != bg_color {
-                    bg_color = pixel.bg_color;
-                    self.set_bg_color(pixel.bg_color);
-                 }
-
-                let bytes = [pixel.c as u8];
-                assert!(writer.write_all(&bytes).is_ok());
+    /// Brings a terminal reached through `writer` up to date with the buffer. Only the cells which have changed
+    /// since the last render are written, moving the cursor and changing colors as little as possible, all in a
+    /// single write.
+    pub fn render_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let mut output = String::new();
+        if self.front.is_none() {
+            output.push_str(&self.esc("2J"));
+            self.colors = None;
+        }
+
+        // Where the cursor is, if known. Writing to the last column of a row can leave it anywhere.
+        let mut cursor = None;
+        let width = self.buffer.first().map_or(0, |row| row.len());
+        for (y, row) in self.buffer.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                // A wide character changes along with its second column, and is drawn over both.
+                if pixel.c == CONTINUATION || self.front.as_ref().is_some_and(|front| front[y][x] == pixel) {
+                    continue;
+                }
+
+                match cursor {
+                    Some((cx, cy)) if cy == y && cx == x => (),
+                    Some((cx, cy)) if cy == y && cx < x => output.push_str(&self.esc(&format!("{}C", x - cx))),
+                    // Console positions are 1-based
+                    _ => output.push_str(&self.esc(&format!("{};{}H", y + 1, x + 1))),
+                }
+
+                match self.colors {
+                    Some((fg, bg)) if fg == pixel.fg_color && bg == pixel.bg_color => (),
+                    Some((fg, _)) if fg == pixel.fg_color => output.push_str(&self.bg_color(pixel.bg_color)),
+                    Some((_, bg)) if bg == pixel.bg_color => output.push_str(&self.fg_color(pixel.fg_color)),
+                    _ => {
+                        output.push_str(&self.fg_color(pixel.fg_color));
+                        output.push_str(&self.bg_color(pixel.bg_color));
+                    },
+                }
+                self.colors = Some((pixel.fg_color, pixel.bg_color));
+
+                output.push(pixel.c);
+                let next = x + char_width(pixel.c);
+                cursor = if next < width { Some((next, y)) } else { None };
             }
-            y += 1;
-            self.set_cursor_pos(0, y);
         }
+        self.front = Some(self.buffer.clone());
 
-        assert!(writer.flush().is_ok());
+        if !output.is_empty() {
+            writer.write_all(output.as_bytes())?;
+            writer.flush()?;
+        }
+        Ok(())
     }
 
-    pub fn set_text(&mut self, text: &'static str, x: u32, y: u32, fg_color: Color, bg_color: Color) {
-        let row = &mut self.buffer[y as usize];
-        let mut i = 0;
+    /// Moves the column that text is drawn relative to, so that a whole game can be drawn beside another.
+    pub fn set_origin(&mut self, x: u32) {
+        self.origin = x;
+    }
+
+    /// Draws text starting at a column and row, where wide characters take up two columns. Text which runs
+    /// past the edge of the display is cut off.
+    pub fn set_text<S: AsRef<str>>(&mut self, text: S, x: u32, y: u32, fg_color: Color, bg_color: Color) {
+        let width = self.buffer[y as usize].len();
+        let mut col = (self.origin + x) as usize;
+
+        for c in text.as_ref().chars() {
+            // Combining and control characters have no column of their own, and are left out.
+            let columns = char_width(c);
+            if columns == 0 {
+                continue;
+            }
+            if col + columns > width {
+                break;
+            }
+            let pixel = Pixel{ c, fg_color, bg_color };
+            self.set_pixel(col, y as usize, pixel);
+            if columns == 2 {
+                self.set_pixel(col + 1, y as usize, Pixel{ c: CONTINUATION, ..pixel });
+            }
+            col += columns;
+        }
+    }
 
-        for c in text.chars() {
-            let cell = &mut row[(x + i) as usize];
-            cell.c = c;
-            cell.fg_color = fg_color;
-            cell.bg_color = bg_color;
-            i += 1;
+    /// Sets a cell of the buffer. Overwriting either half of a wide character blanks the other half.
+    fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) {
+        let row = &mut self.buffer[y];
+        if row[x].c == CONTINUATION && pixel.c != CONTINUATION && x > 0 {
+            row[x - 1].c = ' ';
+        }
+        if char_width(row[x].c) == 2 && x + 1 < row.len() {
+            row[x + 1].c = ' ';
         }
+        row[x] = pixel;
     }
 
-    pub fn clear_screen(&self) {
+    /// Clears the terminal, so that the next render draws the whole buffer again.
+    pub fn clear_screen(&mut self) {
         let mut writer = io::stdout();
         assert!(writer.write_all(self.esc("2J").as_bytes()).is_ok());
         assert!(writer.flush().is_ok());
+        self.front = None;
     }
 
     pub fn clear_buffer(&mut self) {
-        for row in 0..self.buffer.len() {
-            for col in 0..self.buffer[row].len() {
-                self.buffer[row][col].c = ' ';
-                self.buffer[row][col].fg_color = Color::Black;
-                self.buffer[row][col].bg_color = Color::Black;
+        for row in &mut self.buffer {
+            for pixel in row.iter_mut() {
+                *pixel = BLANK;
             }
         }
     }
 
-    fn set_cursor_pos(&self, x: u32, y: u32) {
-        // Console positions are 1-based
-        self.print(&self.esc(&format!("{};{}H", y + 1, x + 1)));
-    }
-
     fn esc(&self, text: &str) -> String { format!("{}[{}", ESC, text) }
 
-    fn print(&self, text: &str) {
-        let mut writer = io::stdout();
-        assert!(writer.write_all(text.as_bytes()).is_ok());
-    }
-
-    fn set_fg_color(&self, color: Color) {
-        self.print(&self.esc(&format!("38;5;{}m", self.get_color_code(color))));
+    fn fg_color(&self, color: Color) -> String {
+        self.esc(&format!("38;5;{}m", self.get_color_code(color)))
     }
 
-    fn set_bg_color(&self, color: Color) {
-        self.print(&self.esc(&format!("48;5;{}m", self.get_color_code(color))));
+    fn bg_color(&self, color: Color) -> String {
+        self.esc(&format!("48;5;{}m", self.get_color_code(color)))
     }
 
     fn get_color_code(&self, color: Color) -> i32 {
@@ -118,7 +170,67 @@ impl Display {
             Color::Red => 9,
             Color::Blue => 21,
             Color::Orange => 202,
+            Color::Gray => 244,
+            Color::Yellow => 226,
+            Color::Magenta => 201,
+            Color::Teal => 30,
+            Color::White => 15,
+            Color::Pink => 218,
+            Color::Brown => 94,
             Color::Black => 0
         }
     }
 }
+
+/// Returns the number of columns a character takes up in the terminal.
+fn char_width(c: char) -> usize {
+    if c == CONTINUATION { 0 } else { c.width().unwrap_or(0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the display, returning what was written.
+    fn render(display: &mut Display) -> String {
+        let mut output = Vec::new();
+        display.render_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn first_render_clears_the_screen() {
+        let mut display = Display::new(4, 3);
+        assert!(render(&mut display).starts_with("\x1b[2J"));
+    }
+
+    #[test]
+    fn unchanged_frame_writes_nothing() {
+        let mut display = Display::new(4, 3);
+        display.set_text("ab", 1, 1, Color::Red, Color::Black);
+        render(&mut display);
+
+        display.clear_buffer();
+        display.set_text("ab", 1, 1, Color::Red, Color::Black);
+        assert_eq!(render(&mut display), "");
+    }
+
+    #[test]
+    fn changed_cell_is_written_alone() {
+        let mut display = Display::new(4, 3);
+        render(&mut display);
+
+        // The colors are those last written, so only the cursor needs to move.
+        display.set_text("x", 2, 1, Color::Black, Color::Black);
+        assert_eq!(render(&mut display), "\x1b[2;3Hx");
+    }
+
+    #[test]
+    fn wide_character_is_written_once() {
+        let mut display = Display::new(4, 3);
+        render(&mut display);
+
+        display.set_text("字x", 0, 0, Color::Black, Color::Black);
+        assert_eq!(render(&mut display), "\x1b[1;1H字x");
+    }
+}
@@ -0,0 +1,1145 @@
+use ai::{self, Placement, Weights, DEFAULT_WEIGHTS};
+use board::*;
+use display::Display;
+use grade::Grader;
+use path::{self, Movement};
+use pc;
+use piece::{Piece, PieceBag};
+use rules::{format_time, Delays, Finesse, LineClear, Mode, Rules, StackVisibility, TSpin};
+use std::cmp;
+use util::*;
+
+/// The ways in which a game can end.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GameOver {
+    /// A new piece overlapped the stack when it spawned.
+    BlockOut,
+    /// A piece locked entirely within the vanish zone.
+    LockOut,
+    /// Garbage pushed the stack out of the top of the vanish zone.
+    TopOut,
+    /// The player reached the mode's final level.
+    Completed,
+}
+
+impl GameOver {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            GameOver::BlockOut => "Block out",
+            GameOver::LockOut => "Lock out",
+            GameOver::TopOut => "Top out",
+            GameOver::Completed => "Final level reached",
+        }
+    }
+}
+
+/// The actions a player can take. Key presses are translated into actions by the `KeyMap`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Hold,
+    Undo,
+    Redo,
+    ToggleHint,
+    TogglePerfectClear,
+    Quit,
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "left" => Some(Action::MoveLeft),
+            "right" => Some(Action::MoveRight),
+            "soft-drop" => Some(Action::SoftDrop),
+            "hard-drop" => Some(Action::HardDrop),
+            "rotate-ccw" => Some(Action::RotateLeft),
+            "rotate-cw" => Some(Action::RotateRight),
+            "rotate-180" => Some(Action::Rotate180),
+            "hold" => Some(Action::Hold),
+            "undo" => Some(Action::Undo),
+            "redo" => Some(Action::Redo),
+            "hint" => Some(Action::ToggleHint),
+            "pc-finder" => Some(Action::TogglePerfectClear),
+            "quit" => Some(Action::Quit),
+            _ => None,
+        }
+    }
+
+    /// Returns true for the actions which should keep repeating while their key is held down. Holding a
+    /// rotation or hold key only acts once.
+    pub fn repeats(&self) -> bool {
+        matches!(*self, Action::MoveLeft | Action::MoveRight | Action::SoftDrop)
+    }
+}
+
+/// The phases a game cycles through for each piece.
+#[derive(Clone)]
+enum Phase {
+    /// The current piece is falling under the player's control.
+    Falling,
+    /// The completed rows are flashing before they are removed, with `frames` frames of a `duration`-frame
+    /// delay left.
+    LineClear{ rows: Vec<usize>, frames: u32, duration: u32 },
+    /// The board is waiting for the next piece to appear (the entry delay, or ARE), with `frames` frames left.
+    Entry{ frames: u32 },
+}
+
+/// A copy of the game's state as a piece spawns, which undo and redo return to.
+#[derive(Clone)]
+struct Snapshot {
+    board: Board,
+    piece_bag: PieceBag,
+    piece: Piece,
+    piece_position: Point,
+    hold: Option<Piece>,
+    hold_used: bool,
+    last_clear: Option<LineClear>,
+    back_to_back: bool,
+    combo: u32,
+    score: u32,
+    lines: u32,
+    level: u32,
+}
+
+/// The number of times moving or rotating a resting piece can restart its lock delay, when the rules allow it.
+const MOVE_RESET_LIMIT: u32 = 15;
+
+pub struct Game {
+    board: Board,
+    rules: Rules,
+    piece_bag: PieceBag,
+    piece: Piece,
+    piece_position: Point,
+    hold: Option<Piece>,
+    /// Set once the player has used hold, preventing it from being used again until the next piece spawns.
+    hold_used: bool,
+    /// Progress towards the next row of gravity, counted in `Gravity::rows` per frame.
+    gravity_counter: u32,
+    /// The number of frames the current piece has been resting on the stack, and the number of times its lock
+    /// delay has been restarted by moving it.
+    lock_frames: u32,
+    lock_resets: u32,
+    /// The direction and index of the kick used if the current piece's last successful move was a rotation,
+    /// as needed to recognise T-spins.
+    last_rotation: Option<(Direction, usize)>,
+    /// The most recent clear worth announcing, and whether the next difficult clear will be back-to-back.
+    last_clear: Option<LineClear>,
+    back_to_back: bool,
+    /// The number of pieces in a row which have cleared lines.
+    combo: u32,
+    /// How the last piece to lock cleared lines, whether or not it was worth announcing.
+    last_lock: Option<LineClear>,
+    phase: Phase,
+    /// The actions whose keys are being held down.
+    held_actions: Vec<Action>,
+    /// Rotations and holds pressed during a delay, which are applied as the next piece spawns.
+    buffered_actions: Vec<Action>,
+    score: u32,
+    lines: u32,
+    level: u32,
+    /// The number of frames played, and the number of pieces locked.
+    frames: u32,
+    pieces: u32,
+    /// Keeps the hidden grade in Master mode.
+    grader: Option<Grader>,
+    /// Snapshots taken as each piece spawned, in modes which allow undo, and the index of the one for the
+    /// current piece.
+    history: Vec<Snapshot>,
+    history_position: usize,
+    /// The AI's suggested placement for the current piece, kept up to date while hints are shown.
+    hint: Option<Placement>,
+    show_hint: bool,
+    /// The weights the AI judges boards with when choosing a hint.
+    hint_weights: Weights,
+    /// The placements the perfect clear finder found for the current piece and those after it, kept up to date
+    /// while the finder is shown.
+    perfect_clear: Option<Vec<Placement>>,
+    show_perfect_clear: bool,
+    /// Where the current piece entered the board and the keys the player has pressed moving it since, which
+    /// are checked against the fewest presses needed once it locks. Placing a piece with too many presses is a
+    /// finesse fault.
+    entry: (Piece, Point),
+    presses: u32,
+    finesse_faults: u32,
+    game_over: Option<GameOver>,
+}
+
+impl Game {
+    /// Starts a game with a random sequence of pieces.
+    pub fn new(rules: Rules) -> Game {
+        Game::with_seed(rules, ::rand::random())
+    }
+
+    /// Starts a game whose pieces are dealt from a bag with the given seed. Games with the same rules and seed
+    /// play out the same way given the same actions on the same frames.
+    pub fn with_seed(rules: Rules, seed: u64) -> Game {
+        let mut piece_bag = PieceBag::new(&rules.piece_set, rules.randomizer, seed);
+        let piece = piece_bag.pop().with_scale(if rules.big { 2 } else { 1 });
+        let entry = (piece.clone(), Point{ x: 0, y: 0 });
+
+        let mut game = Game {
+            board: Board::new(),
+            level: rules.start_level,
+            rules,
+            piece_bag: piece_bag,
+            piece: piece,
+            piece_position: Point{ x: 0, y: 0 },
+            hold: None,
+            hold_used: false,
+            gravity_counter: 0,
+            lock_frames: 0,
+            lock_resets: 0,
+            last_rotation: None,
+            last_clear: None,
+            back_to_back: false,
+            combo: 0,
+            last_lock: None,
+            phase: Phase::Falling,
+            held_actions: Vec::new(),
+            buffered_actions: Vec::new(),
+            score: 0,
+            lines: 0,
+            frames: 0,
+            pieces: 0,
+            grader: None,
+            history: Vec::new(),
+            history_position: 0,
+            hint: None,
+            show_hint: false,
+            hint_weights: DEFAULT_WEIGHTS,
+            perfect_clear: None,
+            show_perfect_clear: false,
+            entry,
+            presses: 0,
+            finesse_faults: 0,
+            game_over: None,
+        };
+
+        if game.rules.mode == Mode::Master {
+            game.grader = Some(Grader::new());
+        }
+        game.place_new_piece();
+        game.save_snapshot();
+        game
+    }
+
+    /// Returns the delays at the current level, with any overrides from the rules.
+    fn delays(&self) -> Delays {
+        let mut delays = self.rules.mode.delays(self.level);
+        if let Some(are) = self.rules.are {
+            delays.are = are;
+        }
+        if let Some(line_clear) = self.rules.line_clear_delay {
+            delays.line_clear = line_clear;
+        }
+        delays
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Returns the piece under the player's control and its position, if there is one.
+    pub fn current_piece(&self) -> Option<(&Piece, Point)> {
+        match self.phase {
+            Phase::Falling if self.game_over.is_none() => Some((&self.piece, self.piece_position)),
+            _ => None,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn lines(&self) -> u32 {
+        self.lines
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Returns how the game ended, if it is over.
+    pub fn game_over(&self) -> Option<GameOver> {
+        self.game_over
+    }
+
+    /// Returns how the last piece to lock cleared lines, if any piece has locked.
+    pub fn last_lock(&self) -> Option<LineClear> {
+        self.last_lock
+    }
+
+    /// Returns the number of frames played so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Returns the number of pieces locked so far.
+    pub fn pieces(&self) -> u32 {
+        self.pieces
+    }
+
+    /// Returns the piece which will be dealt next, at the scale it will be played at.
+    pub fn next_piece(&self) -> Piece {
+        self.piece_bag.peek().with_scale(self.scale())
+    }
+
+    /// Returns the pieces shown in the preview, in the order they will be dealt.
+    pub fn queue(&self) -> Vec<Piece> {
+        self.piece_bag.preview(self.rules.previews).iter().map(|piece| piece.with_scale(self.scale())).collect()
+    }
+
+    /// Returns the piece in hold, if there is one.
+    pub fn held_piece(&self) -> Option<&Piece> {
+        self.hold.as_ref()
+    }
+
+    /// Returns true if the next difficult clear will be back-to-back.
+    pub fn back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+
+    /// Returns the number of pieces in a row which have cleared lines.
+    pub fn combo(&self) -> u32 {
+        self.combo
+    }
+
+    /// Returns the ways the current rules let the player move a piece.
+    pub fn movement(&self) -> Movement {
+        Movement{
+            rotate_180: self.rules.rotate_180,
+            hard_drop: self.rules.hard_drop,
+            instant_gravity: self.rules.mode.gravity(self.level).is_instant(),
+        }
+    }
+
+    /// Finds the shortest sequence of actions which moves the current piece to a placement and locks it there.
+    pub fn find_path(&self, target: &Placement) -> Option<Vec<Action>> {
+        path::find_path(&self.board, &self.piece, self.piece_position, target, self.movement())
+    }
+
+    /// Searches for a perfect clear that can be made from the current board with the current piece, the held
+    /// piece and the pieces in the preview. Returns the placements which make it, if there is one.
+    pub fn find_perfect_clear(&self) -> Option<Vec<Placement>> {
+        if self.rules.big || self.current_piece().is_none() {
+            return None;
+        }
+        let queue = self.queue();
+        let pieces = pc::Pieces{
+            current: &self.piece,
+            origin: self.piece_position,
+            hold: self.hold.as_ref(),
+            queue: &queue,
+            can_hold: self.rules.hold,
+            hold_used: self.hold_used,
+        };
+        pc::solve(&self.board, &pieces, self.movement())
+    }
+
+    /// Returns the new position of the current piece if it were to be dropped.
+    pub fn find_dropped_position(&self) -> Point {
+        let mut origin = self.piece_position;
+        while !self.board.collision_test(&self.piece, origin) {
+            origin.y += self.scale();
+        }
+        origin.y -= self.scale();
+        origin
+    }
+
+    /// Draws the game to the display.
+    pub fn render(&self, display: &mut Display) {
+        // Render the board
+        // The whole stack is revealed once the game is over.
+        let visibility = if self.game_over.is_some() { StackVisibility::Visible } else { self.rules.stack };
+        self.board.render(display, visibility, self.frames);
+
+        // Render the score, level and lines
+        let left_margin = BOARD_WIDTH * 2 + 5;
+        display.set_text(self.rules.mode.name(), left_margin, 0, Color::Red, Color::Black);
+        display.set_text(format!("Score: {}", self.score), left_margin, 2, Color::Red, Color::Black);
+        display.set_text(format!("Level: {}", self.level), left_margin, 3, Color::Red, Color::Black);
+        display.set_text(format!("Lines: {}", self.lines), left_margin, 4, Color::Red, Color::Black);
+        if self.grader.is_some() {
+            display.set_text(format!("Time: {}", format_time(self.frames)), left_margin, 5, Color::Red,
+                             Color::Black);
+        }
+
+        match self.phase {
+            Phase::Falling => {
+                // Render a ghost piece, unless the stack it would land on is hidden
+                if self.rules.ghost && self.rules.stack == StackVisibility::Visible {
+                    let ghost_position = self.find_dropped_position();
+                    self.render_board_piece(display, &self.piece, ghost_position);
+                }
+
+                // Render the suggested placement in outline
+                if let Some(ref hint) = self.hint {
+                    let color = hint.piece.color();
+                    hint.piece.each_point(&mut |row, col| {
+                        if let Some(y) = Board::screen_row(hint.position.y + row) {
+                            let x = (1 + 2 * (hint.position.x + col)) as u32;
+                            display.set_text("[]", x, y, color, Color::Black);
+                        }
+                    });
+                }
+
+                // Number the cells of the pieces making a perfect clear, up to the first which clears lines, as
+                // the rest are placed on a board whose rows have moved.
+                if let Some(ref placements) = self.perfect_clear {
+                    let mut board = self.board.clone();
+                    for (i, placement) in placements.iter().enumerate() {
+                        let color = placement.piece.color();
+                        let label = format!("{0}{0}", i + 1);
+                        for cell in placement.cells() {
+                            if let Some(y) = Board::screen_row(cell.y) {
+                                display.set_text(&label, (1 + 2 * cell.x) as u32, y, color, Color::Black);
+                            }
+                        }
+                        let (next, lines) = placement.result(&board);
+                        if lines > 0 {
+                            break;
+                        }
+                        board = next;
+                    }
+                }
+
+                // Render the currently falling piece
+                self.render_board_piece(display, &self.piece, self.piece_position);
+            },
+            Phase::LineClear{ ref rows, frames, duration } => {
+                let elapsed = duration - frames;
+                self.board.render_clearing_rows(display, rows, visibility, elapsed, duration, self.frames);
+            },
+            Phase::Entry{ .. } => (),
+        }
+
+        match (&self.grader, self.game_over) {
+            // The hidden grade is only revealed at the end of the game, in place of the next and held pieces.
+            (Some(grader), Some(reason)) => {
+                let grade = grader.grade(reason == GameOver::Completed);
+                display.set_text(format!("Grade: {}", grade), left_margin, 6, Color::Red, Color::Black);
+                display.set_text("Section times:", left_margin, 8, Color::Red, Color::Black);
+                for (i, &time) in grader.section_times.iter().enumerate() {
+                    let line = format!("{:03}-{:03}  {}", i * 100, i * 100 + 99, format_time(time));
+                    display.set_text(&line, left_margin + 2, 9 + i as u32, Color::Red, Color::Black);
+                }
+            },
+            _ => {
+                // Render the next pieces, side by side
+                let label = if self.rules.previews > 1 { "Next pieces:" } else { "Next piece:" };
+                display.set_text(label, left_margin, 6, Color::Red, Color::Black);
+                for (i, piece) in self.piece_bag.preview(self.rules.previews).iter().enumerate() {
+                    let x = (left_margin as i32) + 2 + 10 * i as i32;
+                    Game::render_preview(display, piece, Point{ x, y: 7 });
+                }
+
+                // Render the held piece
+                if self.rules.hold {
+                    display.set_text("Hold:", left_margin, 13, Color::Red, Color::Black);
+                    if let Some(ref piece) = self.hold {
+                        Game::render_preview(display, piece, Point{ x: (left_margin as i32) + 2, y: 14 });
+                    }
+                }
+
+                if self.show_perfect_clear {
+                    let found = match self.perfect_clear {
+                        Some(ref placements) => format!("Perfect clear: {} pieces", placements.len()),
+                        None => "No perfect clear".to_string(),
+                    };
+                    display.set_text(&found, left_margin, 17, Color::Red, Color::Black);
+                }
+
+                if self.rules.finesse != Finesse::Off {
+                    let faults = format!("Finesse faults: {}", self.finesse_faults);
+                    display.set_text(&faults, left_margin, 18, Color::Red, Color::Black);
+                }
+            },
+        }
+
+        if let Some(description) = self.last_clear.and_then(|c| c.description()) {
+            display.set_text(&description, left_margin, 19, Color::Red, Color::Black);
+        }
+
+        if let Some(reason) = self.game_over {
+            display.set_text("Game over!", left_margin, 20, Color::Red, Color::Black);
+            display.set_text(reason.description(), left_margin, 21, Color::Red, Color::Black);
+        }
+    }
+
+    /// Draws a preview of a piece, such as the next piece, at its normal size whatever the scale it's played at.
+    pub fn render_preview(display: &mut Display, piece: &Piece, origin: Point) {
+        let color = piece.color();
+
+        piece.with_scale(1).each_point(&mut |row, col| {
+            let x = (origin.x + 2 * col) as u32;
+            let y = (origin.y + row) as u32;
+            display.set_text(" ", x, y, color, color);
+            display.set_text(" ", x + 1, y, color, color);
+        });
+    }
+
+    /// Draws a piece positioned in board coordinates, skipping any cells that fall outside the drawn rows.
+    fn render_board_piece(&self, display: &mut Display, piece: &Piece, position: Point) {
+        let color = piece.color();
+
+        piece.each_point(&mut |row, col| {
+            if let Some(y) = Board::screen_row(position.y + row) {
+                let x = (1 + 2 * (position.x + col)) as u32;
+                display.set_text(" ", x, y, color, color);
+                display.set_text(" ", x + 1, y, color, color);
+            }
+        });
+    }
+
+    /// Moves the current piece in the specified direction. Returns true if the piece could be moved and
+    /// didn't collide.
+    fn move_piece(&mut self, x: i32, y: i32) -> bool {
+        let new_position = Point{
+            x: self.piece_position.x + x,
+            y: self.piece_position.y + y,
+        };
+        if self.board.collision_test(&self.piece, new_position) {
+            false
+        } else {
+            self.piece_position = new_position;
+            self.last_rotation = None;
+            if y > 0 {
+                self.lock_frames = 0;
+            }
+            true
+        }
+    }
+
+    /// Rotates the current piece in the specified direction, trying each of the piece's kick offsets in turn.
+    /// Returns true if the piece could be rotated without any collisions.
+    fn rotate_piece(&mut self, direction: Direction) -> bool {
+        if direction == Direction::Half && !self.rules.rotate_180 {
+            return false;
+        }
+
+        match self.board.rotate_piece(&self.piece, self.piece_position, direction) {
+            Some((piece, position, kick)) => {
+                self.piece = piece;
+                self.piece_position = position;
+                self.last_rotation = Some((direction, kick));
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Positions the current piece in the vanish zone so that its lowest blocks sit on the row just above the
+    /// visible field, then drops it one row if nothing is in the way. Returns false, ending the game with a
+    /// block out, if the piece overlaps the stack (unless the mode has no game over).
+    ///
+    /// If a rotation key is being held as the piece spawns, the piece starts out rotated (an initial rotation),
+    /// provided it fits that way. At 20G the piece then falls straight onto the stack.
+    fn place_new_piece(&mut self) -> bool {
+        self.gravity_counter = 0;
+        self.lock_frames = 0;
+        self.lock_resets = 0;
+        self.last_rotation = None;
+
+        if let Some(direction) = self.initial_rotation() {
+            let mut rotated = self.piece.clone();
+            rotated.rotate(direction);
+            if !self.board.collision_test(&rotated, Game::spawn_position(&rotated)) {
+                self.piece = rotated;
+            }
+        }
+
+        let origin = Game::spawn_position(&self.piece);
+        if self.board.collision_test(&self.piece, origin) && !self.top_out(GameOver::BlockOut) {
+            return false;
+        }
+        self.piece_position = origin;
+        self.move_piece(0, self.scale());
+        self.apply_instant_gravity();
+        self.entry = (self.piece.clone(), self.piece_position);
+        self.presses = 0;
+        self.update_hint();
+        self.update_perfect_clear();
+        true
+    }
+
+    /// Asks the AI for the best placement of the current piece, if hints are being shown.
+    fn update_hint(&mut self) {
+        self.hint = if self.show_hint {
+            let next = self.next_piece();
+            ai::best_placement(&self.board, &self.piece, self.piece_position, Some(&next), self.movement(),
+                               &self.hint_weights)
+        } else {
+            None
+        };
+    }
+
+    /// Searches for a perfect clear, if the finder is being shown.
+    fn update_perfect_clear(&mut self) {
+        self.perfect_clear = if self.show_perfect_clear { self.find_perfect_clear() } else { None };
+    }
+
+    /// Ends the game because the stack has grown too high. In modes without game over the board is cleared
+    /// instead and this returns true.
+    fn top_out(&mut self, reason: GameOver) -> bool {
+        if self.rules.mode.can_top_out() {
+            self.game_over = Some(reason);
+            false
+        } else {
+            self.board = Board::new();
+            true
+        }
+    }
+
+    /// Returns the position in the vanish zone at which a piece spawns.
+    pub fn spawn_position(piece: &Piece) -> Point {
+        let mut bottom = 0;
+        piece.each_point(&mut |row, _| bottom = cmp::max(bottom, row));
+
+        // Big pieces stay aligned to the half-resolution field.
+        let scale = piece.scale() as u32;
+        Point{
+            x: ((BOARD_WIDTH / scale - (piece.size() as u32) / scale) / 2 * scale) as i32,
+            y: (HIDDEN_ROWS as i32) - 1 - bottom,
+        }
+    }
+
+    /// Returns the direction of the rotation key being held, if initial rotation is allowed.
+    fn initial_rotation(&self) -> Option<Direction> {
+        if !self.rules.irs {
+            return None;
+        }
+        self.held_actions.iter().chain(self.buffered_actions.iter()).filter_map(|action| {
+            match *action {
+                Action::RotateLeft => Some(Direction::Left),
+                Action::RotateRight => Some(Direction::Right),
+                Action::Rotate180 if self.rules.rotate_180 => Some(Direction::Half),
+                _ => None,
+            }
+        }).next()
+    }
+
+    /// The number of board cells across each block of a piece: 2 in big mode, where the field is played at half
+    /// resolution.
+    fn scale(&self) -> i32 {
+        if self.rules.big { 2 } else { 1 }
+    }
+
+    /// Takes the next piece from the bag, at the scale the game is played at.
+    fn deal_piece(&mut self) -> Piece {
+        let scale = self.scale();
+        self.piece_bag.pop().with_scale(scale)
+    }
+
+    /// Deals the next piece from the bag and places it on the board. If the hold key is being held, the
+    /// piece is swapped into hold before it appears (an initial hold).
+    fn spawn_next_piece(&mut self) -> bool {
+        self.piece = self.deal_piece();
+        self.hold_used = false;
+        self.phase = Phase::Falling;
+        let level = self.rules.mode.level_after_spawn(self.level);
+        self.set_level(level);
+
+        let hold = self.held_actions.contains(&Action::Hold) || self.buffered_actions.contains(&Action::Hold);
+        let spawned = if self.rules.hold && self.rules.ihs && hold {
+            self.hold_piece()
+        } else {
+            self.place_new_piece()
+        };
+        self.buffered_actions.clear();
+        if spawned {
+            self.save_snapshot();
+        }
+        spawned
+    }
+
+    /// Records the state of the game as a new piece spawns, discarding anything which was undone before it.
+    fn save_snapshot(&mut self) {
+        if !self.rules.mode.allows_undo() {
+            return;
+        }
+
+        let snapshot = Snapshot{
+            board: self.board.clone(),
+            piece_bag: self.piece_bag.clone(),
+            piece: self.piece.clone(),
+            piece_position: self.piece_position,
+            hold: self.hold.clone(),
+            hold_used: self.hold_used,
+            last_clear: self.last_clear,
+            back_to_back: self.back_to_back,
+            combo: self.combo,
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+        };
+        if !self.history.is_empty() {
+            self.history.truncate(self.history_position + 1);
+        }
+        self.history.push(snapshot);
+        self.history_position = self.history.len() - 1;
+    }
+
+    /// Returns the game to the snapshot `offset` places away from the current one in the history, undoing or
+    /// redoing piece placements. Returns false if there is no such snapshot.
+    fn restore_snapshot(&mut self, offset: isize) -> bool {
+        let position = self.history_position as isize + offset;
+        if position < 0 || position >= self.history.len() as isize {
+            return false;
+        }
+        self.history_position = position as usize;
+
+        let snapshot = self.history[self.history_position].clone();
+        self.board = snapshot.board;
+        self.piece_bag = snapshot.piece_bag;
+        self.piece = snapshot.piece;
+        self.piece_position = snapshot.piece_position;
+        self.hold = snapshot.hold;
+        self.hold_used = snapshot.hold_used;
+        self.last_clear = snapshot.last_clear;
+        self.back_to_back = snapshot.back_to_back;
+        self.combo = snapshot.combo;
+        self.score = snapshot.score;
+        self.lines = snapshot.lines;
+        self.level = snapshot.level;
+
+        self.phase = Phase::Falling;
+        self.gravity_counter = 0;
+        self.lock_frames = 0;
+        self.lock_resets = 0;
+        self.last_rotation = None;
+        self.buffered_actions.clear();
+        // Finesse is counted from where the restored piece stands, as if it had just spawned there.
+        self.entry = (self.piece.clone(), self.piece_position);
+        self.presses = 0;
+        self.update_hint();
+        self.update_perfect_clear();
+        true
+    }
+
+    /// Moves the game on to a new level, recording the times of any sections completed.
+    fn set_level(&mut self, level: u32) {
+        if let Some(ref mut grader) = self.grader {
+            grader.level_up(self.level, level, self.frames);
+        }
+        self.level = level;
+    }
+
+    /// Starts the entry delay before the next piece, or spawns it straight away if there is none.
+    fn start_entry_delay(&mut self) -> bool {
+        let are = self.delays().are;
+        if are > 0 {
+            self.phase = Phase::Entry{ frames: are };
+            true
+        } else {
+            self.spawn_next_piece()
+        }
+    }
+
+    /// Locks the current piece into the board and scores any completed lines, then starts the line clear and
+    /// entry delays which come before the next piece. Returns false, ending the game with a lock out, if the
+    /// piece locked entirely within the vanish zone.
+    fn lock_piece(&mut self) -> bool {
+        if !self.check_finesse() {
+            return true;
+        }
+
+        let mut visible = false;
+        let top = self.piece_position.y;
+        self.piece.each_point(&mut |row, _| visible = visible || top + row >= HIDDEN_ROWS as i32);
+
+        let t_spin = self.detect_t_spin();
+        self.board.lock_piece(&self.piece, self.piece_position, self.frames);
+        self.pieces += 1;
+        if !visible && !self.top_out(GameOver::LockOut) {
+            return false;
+        }
+
+        let rows = self.board.full_rows();
+        let mut clear = LineClear{
+            lines: (rows.len() as u32).div_ceil(self.scale() as u32),
+            t_spin,
+            back_to_back: false,
+        };
+        if clear.is_difficult() {
+            clear.back_to_back = self.back_to_back;
+            self.back_to_back = true;
+        } else if clear.lines > 0 {
+            self.back_to_back = false;
+        }
+        self.combo = if clear.lines > 0 { self.combo + 1 } else { 0 };
+        if clear.description().is_some() {
+            self.last_clear = Some(clear);
+        }
+        self.last_lock = Some(clear);
+
+        self.score += self.rules.mode.clear_score(&clear, self.level);
+        self.lines += clear.lines;
+        if let Some(ref mut grader) = self.grader {
+            grader.clear(clear.lines, self.level);
+        }
+        let level = self.rules.mode.level_after_clear(self.level, self.rules.start_level, self.lines, clear.lines);
+        self.set_level(level);
+        if Some(self.level) == self.rules.mode.final_level() {
+            self.board.remove_rows(&rows);
+            self.game_over = Some(GameOver::Completed);
+            return false;
+        }
+
+        if !rows.is_empty() {
+            let duration = self.delays().line_clear;
+            if duration > 0 {
+                self.phase = Phase::LineClear{ rows, frames: duration, duration };
+                return true;
+            }
+            self.board.remove_rows(&rows);
+        }
+        self.start_entry_delay()
+    }
+
+    /// Counts a finesse fault if the player pressed more keys placing the current piece than it needed. When
+    /// practicing, the piece then goes back to where it entered the board to be placed again, and this returns
+    /// false to stop it from locking.
+    fn check_finesse(&mut self) -> bool {
+        if self.rules.finesse == Finesse::Off {
+            return true;
+        }
+        let target = Placement{ piece: self.piece.clone(), position: self.piece_position, hold: false };
+        let (piece, origin) = self.entry.clone();
+        let needed = path::finesse_presses(&self.board, &piece, origin, &target, self.movement());
+        if needed.is_none_or(|needed| self.presses as usize <= needed) {
+            return true;
+        }
+
+        self.finesse_faults += 1;
+        if self.rules.finesse != Finesse::Practice {
+            return true;
+        }
+        self.piece = piece;
+        self.piece_position = origin;
+        self.presses = 0;
+        self.gravity_counter = 0;
+        self.lock_frames = 0;
+        self.lock_resets = 0;
+        self.last_rotation = None;
+        false
+    }
+
+    /// Checks whether the current piece, about to lock, has made a T-spin. The piece must be the tetromino T
+    /// whose last move was a rotation, with at least three of the four corners around its center blocked. It is
+    /// only a mini T-spin if one of the two corners it points towards is open, unless it got there with the
+    /// final kick of a quarter turn.
+    fn detect_t_spin(&self) -> TSpin {
+        let (direction, kick) = match self.last_rotation {
+            Some(r) => r,
+            None => return TSpin::None,
+        };
+        // Big pieces are checked block by block, on the half-resolution field.
+        let scale = self.piece.scale();
+        if self.piece.name() != "T" || self.piece.size() / scale as usize != 3 || self.piece.blocks() != 4 {
+            return TSpin::None;
+        }
+
+        // The center is the block the other three are joined to. Where it sits in the box depends on the rotation
+        // system: ARS keeps the T at the bottom of its box when it points up.
+        let is_block = |row: i32, col: i32| self.piece.is_block(row * scale, col * scale);
+        let sides = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+        let center = (0..9).map(|i| (i % 3, i / 3)).find(|&(col, row)| {
+            is_block(row, col) && sides.iter().filter(|&&(dx, dy)| is_block(row + dy, col + dx)).count() == 3
+        });
+        let (center_x, center_y) = match center {
+            Some(center) => center,
+            None => return TSpin::None,
+        };
+
+        let blocked = |dx: i32, dy: i32| {
+            let x = self.piece_position.x + (center_x + dx) * scale;
+            let y = self.piece_position.y + (center_y + dy) * scale;
+            x < 0 || x >= BOARD_WIDTH as i32 || y < 0 || y >= BOARD_HEIGHT as i32 ||
+                self.board.cells[y as usize][x as usize].is_some()
+        };
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+        if corners.iter().filter(|&&(dx, dy)| blocked(dx, dy)).count() < 3 {
+            return TSpin::None;
+        }
+
+        // The T points away from the one side of its center which has no block.
+        let &(back_x, back_y) = sides.iter().find(|&&(dx, dy)| !is_block(center_y + dy, center_x + dx)).unwrap();
+        let (front_x, front_y) = (-back_x, -back_y);
+        let front_blocked = corners.iter()
+            .filter(|&&(dx, dy)| (front_x != 0 && dx == front_x) || (front_y != 0 && dy == front_y))
+            .all(|&(dx, dy)| blocked(dx, dy));
+
+        if front_blocked || (direction != Direction::Half && kick == 4) {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
+    /// Raises the stack by `lines` rows of garbage with a gap in column `hole`. The current piece is pushed up
+    /// with the stack if it would otherwise overlap it. Returns false, ending the game with a top out, if any
+    /// blocks are pushed off the top of the board (unless the mode has no game over).
+    pub fn add_garbage(&mut self, lines: u32, hole: u32) -> bool {
+        // In big mode each line of garbage is a block high, with a hole a block wide.
+        let scale = self.scale() as u32;
+        let inserted = self.board.insert_garbage(lines * scale, hole / scale * scale, scale, self.frames);
+        if !inserted && !self.top_out(GameOver::TopOut) {
+            return false;
+        }
+
+        while self.board.collision_test(&self.piece, self.piece_position) {
+            if self.piece_position.y < 0 {
+                return self.top_out(GameOver::TopOut);
+            }
+            self.piece_position.y -= self.scale();
+        }
+
+        true
+    }
+
+    /// Advances the game by moving the current piece down one step. If the piece cannot move down, the piece
+    /// is locked and the game is set up to drop the next piece.  Returns true if the game could be advanced,
+    /// false if the player has lost.
+    fn advance_game(&mut self) -> bool {
+        if self.game_over.is_some() {
+            return false;
+        }
+
+        if !self.move_piece(0, self.scale()) {
+            return self.lock_piece();
+        }
+
+        true
+    }
+
+    /// Counts down a frame of the line clear or entry delay, moving on to the next phase once it is over.
+    /// Returns true if anything changed.
+    fn update_delay(&mut self) -> bool {
+        match self.phase.clone() {
+            Phase::Falling => false,
+            Phase::LineClear{ rows, frames, duration } => {
+                if frames > 1 {
+                    self.phase = Phase::LineClear{ rows, frames: frames - 1, duration };
+                } else {
+                    self.board.remove_rows(&rows);
+                    self.start_entry_delay();
+                }
+                true
+            },
+            Phase::Entry{ frames } => {
+                if frames > 1 {
+                    self.phase = Phase::Entry{ frames: frames - 1 };
+                    false
+                } else {
+                    self.spawn_next_piece();
+                    true
+                }
+            },
+        }
+    }
+
+    /// Tells the game which actions' keys are currently held down. These are checked when a new piece spawns
+    /// to apply initial rotation and hold.
+    pub fn set_held_actions(&mut self, actions: Vec<Action>) {
+        self.held_actions = actions;
+    }
+
+    /// Sets the weights the AI judges boards with when showing a hint.
+    pub fn set_hint_weights(&mut self, weights: Weights) {
+        self.hint_weights = weights;
+        self.update_hint();
+    }
+
+    /// Runs a single frame of the game, applying gravity to the current piece. A piece locks once it has
+    /// rested on the stack for the lock delay or, if there is none, as soon as gravity pulls on it while it
+    /// rests. Returns true if anything changed.
+    pub fn update(&mut self) -> bool {
+        if self.game_over.is_some() {
+            return false;
+        }
+        self.frames += 1;
+        // The timer shown in Master mode and a fading stack change the display every frame.
+        let timed = match self.rules.stack {
+            StackVisibility::Fading(_) => true,
+            _ => self.grader.is_some(),
+        };
+
+        match self.phase {
+            Phase::Falling => (),
+            _ => return self.update_delay() || timed,
+        }
+        if let Some(ref mut grader) = self.grader {
+            grader.update();
+        }
+
+        let gravity = self.rules.mode.gravity(self.level);
+        let lock_delay = self.delays().lock;
+        let mut changed = timed;
+        self.gravity_counter += gravity.rows;
+        if self.gravity_counter >= gravity.frames {
+            changed = true;
+            let mut moved = false;
+            while self.gravity_counter >= gravity.frames {
+                self.gravity_counter -= gravity.frames;
+                if self.move_piece(0, self.scale()) {
+                    moved = true;
+                } else {
+                    self.gravity_counter = 0;
+                    if !moved && lock_delay == 0 {
+                        self.advance_game();
+                        return true;
+                    }
+                    break;
+                }
+            }
+        }
+
+        if lock_delay > 0 && self.is_resting() {
+            self.lock_frames += 1;
+            if self.lock_frames >= lock_delay {
+                self.lock_piece();
+                return true;
+            }
+        }
+
+        changed
+    }
+
+    /// Returns true if the current piece is resting on the stack or the floor.
+    fn is_resting(&self) -> bool {
+        let below = Point{ x: self.piece_position.x, y: self.piece_position.y + self.scale() };
+        self.board.collision_test(&self.piece, below)
+    }
+
+    /// Drops the current piece straight onto the stack if the gravity is 20G.
+    fn apply_instant_gravity(&mut self) {
+        if self.rules.mode.gravity(self.level).is_instant() {
+            let position = self.find_dropped_position();
+            if position != self.piece_position {
+                self.piece_position = position;
+                self.lock_frames = 0;
+                self.last_rotation = None;
+            }
+        }
+    }
+
+    /// Moves the current piece down one row, locking it if it has landed.
+    fn soft_drop(&mut self) -> bool {
+        if self.move_piece(0, self.scale()) {
+            self.score += self.rules.mode.soft_drop_score();
+            true
+        } else {
+            self.advance_game()
+        }
+    }
+
+    /// Drops the current piece to the lowest spot on the board where it fits without collisions and
+    /// advances the game.
+    fn drop_piece(&mut self) -> bool {
+        while self.move_piece(0, self.scale()) {
+            self.score += self.rules.mode.hard_drop_score();
+        }
+        self.advance_game()
+    }
+
+    /// Swaps the current piece with the held piece, or with the next piece if nothing is held yet. This can
+    /// only be done once per piece.
+    fn hold_piece(&mut self) -> bool {
+        if self.hold_used {
+            return false;
+        }
+
+        let mut held = self.piece.clone();
+        held.reset_rotation();
+        self.piece = match self.hold.take() {
+            Some(piece) => piece,
+            None => self.deal_piece(),
+        };
+        self.hold = Some(held);
+        self.hold_used = true;
+        self.place_new_piece()
+    }
+
+    /// Performs an action for a key pressed by the player, `repeat` being set if the press is an automatic
+    /// repeat of a held key. Presses that move the current piece are counted for checking finesse, but a held
+    /// key only counts once.
+    pub fn press(&mut self, action: Action, repeat: bool) -> bool {
+        let moves_piece = matches!(action, Action::MoveLeft | Action::MoveRight | Action::SoftDrop |
+                                           Action::HardDrop | Action::RotateLeft | Action::RotateRight |
+                                           Action::Rotate180);
+        if let Phase::Falling = self.phase {
+            if moves_piece && !repeat {
+                self.presses += 1;
+            }
+        }
+        self.perform(action)
+    }
+
+    /// Performs a player action. Returns true if the action changed the game. During the delays between
+    /// pieces there is no piece to act on, so rotations and holds are saved for when the next piece spawns.
+    pub fn perform(&mut self, action: Action) -> bool {
+        if self.game_over.is_some() {
+            return false;
+        }
+        match action {
+            Action::Undo => return self.restore_snapshot(-1),
+            Action::Redo => return self.restore_snapshot(1),
+            Action::ToggleHint => {
+                self.show_hint = !self.show_hint;
+                self.update_hint();
+                return true;
+            },
+            Action::TogglePerfectClear => {
+                self.show_perfect_clear = !self.show_perfect_clear;
+                self.update_perfect_clear();
+                return true;
+            },
+            _ => (),
+        }
+        match self.phase {
+            Phase::Falling => (),
+            _ => {
+                match action {
+                    Action::RotateLeft | Action::RotateRight | Action::Rotate180 | Action::Hold => {
+                        self.buffered_actions.push(action);
+                    },
+                    _ => (),
+                }
+                return false;
+            },
+        }
+
+        let changed = match action {
+            Action::MoveLeft => self.move_piece(-self.scale(), 0),
+            Action::MoveRight => self.move_piece(self.scale(), 0),
+            Action::SoftDrop => self.soft_drop(),
+            Action::HardDrop if self.rules.hard_drop => self.drop_piece(),
+            Action::RotateLeft => self.rotate_piece(Direction::Left),
+            Action::RotateRight => self.rotate_piece(Direction::Right),
+            Action::Rotate180 => self.rotate_piece(Direction::Half),
+            Action::Hold if self.rules.hold => self.hold_piece(),
+            _ => false,
+        };
+
+        if changed && self.game_over.is_none() {
+            if let Phase::Falling = self.phase {
+                if self.rules.move_reset && self.lock_frames > 0 && self.lock_resets < MOVE_RESET_LIMIT {
+                    self.lock_frames = 0;
+                    self.lock_resets += 1;
+                }
+                self.apply_instant_gravity();
+            }
+        }
+        changed
+    }
+}
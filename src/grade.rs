@@ -0,0 +1,162 @@
+use rules::FRAMES_PER_SECOND;
+
+/// The grade points needed to go up an internal grade.
+const POINTS_PER_GRADE: u32 = 100;
+
+/// The number of levels in a section. The time taken over each section is recorded.
+const LEVELS_PER_SECTION: u32 = 100;
+
+/// The time allowed for each section, in frames, for a player at the top grade to be awarded GM.
+const SECTION_TIME_LIMIT: u32 = 65 * FRAMES_PER_SECOND;
+
+/// For each internal grade: the number of frames it takes for a grade point to decay, and the points
+/// awarded for clearing one to four lines at once. These follow TGM2's Master mode.
+const GRADE_TABLE: [(u32, [u32; 4]); 31] = [
+    (125, [10, 20, 40, 50]),
+    (80, [10, 20, 30, 40]),
+    (80, [10, 20, 30, 40]),
+    (50, [10, 15, 30, 40]),
+    (45, [10, 15, 20, 40]),
+    (45, [5, 15, 20, 30]),
+    (45, [5, 10, 20, 30]),
+    (40, [5, 10, 15, 30]),
+    (40, [5, 10, 15, 30]),
+    (40, [5, 10, 15, 30]),
+    (40, [2, 12, 13, 30]),
+    (40, [2, 12, 13, 30]),
+    (30, [2, 12, 13, 30]),
+    (30, [2, 12, 13, 30]),
+    (30, [2, 12, 13, 30]),
+    (20, [2, 12, 13, 30]),
+    (20, [2, 12, 13, 30]),
+    (20, [2, 12, 13, 30]),
+    (20, [2, 12, 13, 30]),
+    (20, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (15, [2, 12, 13, 30]),
+    (10, [2, 12, 13, 30]),
+];
+
+/// The grade shown to the player for each internal grade. The last internal grade has no points table as it
+/// can't be passed.
+const GRADE_NAMES: [&str; 32] = [
+    "9", "8", "7", "6", "5", "4", "4", "3", "3", "2", "2", "2", "1", "1", "1", "S1", "S1", "S1", "S2", "S3", "S4",
+    "S4", "S4", "S5", "S5", "S6", "S6", "S7", "S7", "S8", "S8", "S9",
+];
+
+/// Multipliers, in tenths, applied to grade points for clearing one to four lines while on a combo of
+/// consecutive clears, indexed by the length of the combo.
+const COMBO_MULTIPLIERS: [[u32; 4]; 10] = [
+    [10, 10, 10, 10],
+    [10, 10, 10, 10],
+    [12, 14, 15, 10],
+    [12, 15, 18, 10],
+    [14, 16, 20, 10],
+    [14, 17, 22, 10],
+    [14, 18, 23, 10],
+    [14, 19, 24, 10],
+    [15, 20, 25, 10],
+    [20, 25, 30, 10],
+];
+
+/// Keeps the hidden grade of a Master mode game.
+///
+/// Clearing lines earns grade points, more of them for clearing several lines at once, for combos of clears
+/// and at higher levels, and every hundred points moves the player up an internal grade. The points decay
+/// while the player isn't on a combo, faster at higher grades. Reaching the final level at the top grade with
+/// every section cleared quickly enough is rewarded with the grade of GM.
+pub struct Grader {
+    grade: usize,
+    points: u32,
+    /// Frames counted towards the decay of the next grade point.
+    decay: u32,
+    /// The number of consecutive pieces which have cleared lines.
+    combo: u32,
+    /// The frame on which the current section started.
+    section_start: u32,
+    /// The time taken over each completed section, in frames.
+    pub section_times: Vec<u32>,
+}
+
+impl Default for Grader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Grader {
+    pub fn new() -> Grader {
+        Grader{
+            grade: 0,
+            points: 0,
+            decay: 0,
+            combo: 0,
+            section_start: 0,
+            section_times: Vec::new(),
+        }
+    }
+
+    /// Runs a frame in which the player is controlling a piece, letting grade points decay.
+    pub fn update(&mut self) {
+        if self.combo > 0 || self.points == 0 || self.grade >= GRADE_TABLE.len() {
+            return;
+        }
+
+        self.decay += 1;
+        if self.decay >= GRADE_TABLE[self.grade].0 {
+            self.decay = 0;
+            self.points -= 1;
+        }
+    }
+
+    /// Awards grade points for a piece which locked at `level`, clearing `lines` lines.
+    pub fn clear(&mut self, lines: u32, level: u32) {
+        if lines == 0 {
+            self.combo = 0;
+            return;
+        }
+        self.combo += 1;
+        if self.grade >= GRADE_TABLE.len() {
+            return;
+        }
+
+        let lines = ::std::cmp::min(lines, 4) as usize;
+        let combo = ::std::cmp::min(self.combo as usize, COMBO_MULTIPLIERS.len() - 1);
+        let base = GRADE_TABLE[self.grade].1[lines - 1];
+        self.points += base * COMBO_MULTIPLIERS[combo][lines - 1] / 10 * (1 + level / 250);
+        if self.points >= POINTS_PER_GRADE {
+            self.grade += 1;
+            self.points = 0;
+            self.decay = 0;
+        }
+    }
+
+    /// Records the time of any section completed by going from level `from` to level `to`, `frames` frames
+    /// into the game.
+    pub fn level_up(&mut self, from: u32, to: u32, frames: u32) {
+        let last_level = to == 999 && from < 999;
+        if to / LEVELS_PER_SECTION > from / LEVELS_PER_SECTION || last_level {
+            self.section_times.push(frames - self.section_start);
+            self.section_start = frames;
+        }
+    }
+
+    /// Returns the name of the player's grade. `completed` is set if the player reached the final level.
+    pub fn grade(&self, completed: bool) -> &'static str {
+        let sections = (999 / LEVELS_PER_SECTION + 1) as usize;
+        if completed && self.grade == GRADE_NAMES.len() - 1 && self.section_times.len() == sections &&
+            self.section_times.iter().all(|&time| time <= SECTION_TIME_LIMIT) {
+            "GM"
+        } else {
+            GRADE_NAMES[self.grade]
+        }
+    }
+}
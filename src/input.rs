@@ -0,0 +1,198 @@
+use game::Action;
+use std;
+use std::time::{Duration, Instant};
+
+/// The longest gap between two presses of a key for the second to be taken as the terminal auto-repeating a
+/// key which is being held down.
+const REPEAT_INTERVAL_MS: u64 = 100;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Space,
+    CtrlC,
+    Char(char),
+}
+
+impl Key {
+    /// Looks up a key by name: `up`, `down`, `left`, `right`, `space`, or any single character.
+    pub fn from_name(name: &str) -> Option<Key> {
+        match name {
+            "up" => Some(Key::Up),
+            "down" => Some(Key::Down),
+            "left" => Some(Key::Left),
+            "right" => Some(Key::Right),
+            "space" => Some(Key::Space),
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(Key::Char(c)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Maps keys to the game actions they perform.
+pub struct KeyMap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl KeyMap {
+    /// Returns the default key bindings. W, A, S and D work the same as the arrow keys.
+    pub fn new() -> KeyMap {
+        KeyMap{
+            bindings: vec![
+                (Key::Left, Action::MoveLeft),
+                (Key::Char('a'), Action::MoveLeft),
+                (Key::Right, Action::MoveRight),
+                (Key::Char('d'), Action::MoveRight),
+                (Key::Down, Action::SoftDrop),
+                (Key::Char('s'), Action::SoftDrop),
+                (Key::Space, Action::HardDrop),
+                (Key::Up, Action::RotateLeft),
+                (Key::Char('w'), Action::RotateLeft),
+                (Key::Char('q'), Action::RotateLeft),
+                (Key::Char('e'), Action::RotateRight),
+                (Key::Char('r'), Action::Rotate180),
+                (Key::Char('c'), Action::Hold),
+                (Key::Char('u'), Action::Undo),
+                (Key::Char('y'), Action::Redo),
+                (Key::Char('h'), Action::ToggleHint),
+                (Key::Char('p'), Action::TogglePerfectClear),
+                (Key::Char('z'), Action::Quit),
+                (Key::CtrlC, Action::Quit),
+            ]
+        }
+    }
+
+    /// Returns the bindings for one of two players sharing the keyboard: the first player, on the left, moves
+    /// with W, A, S and D and the second with the arrow keys.
+    pub fn for_player(player: usize) -> KeyMap {
+        let keys = if player == 0 {
+            [Key::Char('a'), Key::Char('d'), Key::Char('s'), Key::Char('w'), Key::Char('q'), Key::Char('e'),
+             Key::Char('r'), Key::Char('c')]
+        } else {
+            [Key::Left, Key::Right, Key::Down, Key::Up, Key::Char(','), Key::Char('.'), Key::Char('/'),
+             Key::Char('m')]
+        };
+        let actions = [Action::MoveLeft, Action::MoveRight, Action::SoftDrop, Action::HardDrop, Action::RotateLeft,
+                       Action::RotateRight, Action::Rotate180, Action::Hold];
+        let mut bindings: Vec<(Key, Action)> = keys.iter().cloned().zip(actions.iter().cloned()).collect();
+        bindings.push((Key::CtrlC, Action::Quit));
+        KeyMap{ bindings }
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.bindings.iter().find(|&&(k, _)| k == key).map(|&(_, a)| a)
+    }
+
+    /// Binds a key to an action, replacing the action's existing keys and anything else the key was bound to.
+    /// Ctrl-C always quits.
+    pub fn bind(&mut self, key: Key, action: Action) {
+        self.bindings.retain(|&(k, a)| k != key && (a != action || k == Key::CtrlC));
+        self.bindings.push((key, action));
+    }
+
+    /// Parses and applies a binding written as `<action>=<key>`, such as `rotate-180=x`. Returns false if the
+    /// binding isn't valid.
+    pub fn bind_str(&mut self, binding: &str) -> bool {
+        let mut parts = binding.splitn(2, '=');
+        match (parts.next().and_then(Action::from_name), parts.next().and_then(Key::from_name)) {
+            (Some(action), Some(key)) => {
+                self.bind(key, action);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tracks which keys are being held down.
+///
+/// Terminals only report key presses, not releases, so a key is taken to be held while the terminal is
+/// auto-repeating it: that is, while presses of the key keep arriving in quick succession. This means a key
+/// isn't seen as held until the terminal's repeat delay has passed and it begins repeating.
+pub struct KeyState {
+    /// Each key seen, the time it was last pressed, and whether that press was a repeat.
+    keys: Vec<(Key, Instant, bool)>,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyState {
+    pub fn new() -> KeyState {
+        KeyState{
+            keys: Vec::new()
+        }
+    }
+
+    /// Records a press of a key. Returns true if the press was an automatic repeat of a held key.
+    pub fn press(&mut self, key: Key, now: Instant) -> bool {
+        let interval = Duration::from_millis(REPEAT_INTERVAL_MS);
+        if let Some(entry) = self.keys.iter_mut().find(|entry| entry.0 == key) {
+            let repeat = now.duration_since(entry.1) < interval;
+            entry.1 = now;
+            entry.2 = repeat;
+            return repeat;
+        }
+        self.keys.push((key, now, false));
+        false
+    }
+
+    /// Returns the keys which are currently being held down.
+    pub fn held(&self, now: Instant) -> Vec<Key> {
+        let interval = Duration::from_millis(REPEAT_INTERVAL_MS);
+        self.keys.iter()
+            .filter(|&&(_, last, repeating)| repeating && now.duration_since(last) < interval)
+            .map(|&(key, _, _)| key)
+            .collect()
+    }
+}
+
+pub fn get_input(stdin: &mut std::io::Stdin) -> Option<Key> {
+    use std::io::Read;
+
+    let c = &mut [0u8];
+    match stdin.read(c) {
+        Ok(_) => {
+            match std::str::from_utf8(c) {
+                Ok(" ") => Some(Key::Space),
+                Ok("\x03") => Some(Key::CtrlC),
+                // Escape sequence started - must read two more bytes.
+                Ok("\x1b") => {
+                    let code = &mut [0u8; 2];
+                    match stdin.read(code) {
+                        Ok(_) => {
+                            match std::str::from_utf8(code) {
+                                Ok("[A") => Some(Key::Up),
+                                Ok("[B") => Some(Key::Down),
+                                Ok("[C") => Some(Key::Right),
+                                Ok("[D") => Some(Key::Left),
+                                _ => None
+                            }
+                        },
+                        Err(msg) => panic!(format!("could not read from standard in: {}", msg))
+                    }
+                },
+                Ok(n) => Some(Key::Char(n.chars().next().unwrap())),
+                _ => None
+            }
+        },
+        Err(msg) => panic!(format!("could not read from standard in: {}", msg))
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value, as exchanged with bots, other players and tools.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// An object's members, in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from its members.
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    /// Parses a JSON document. Returns an error describing the problem if it isn't valid.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser{ chars: text.chars().peekable(), depth: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected '{}' after the end of the JSON value", c)),
+        }
+    }
+
+    /// Returns the member of an object with the given key.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an integer, if it is a whole number.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Json::Number(n) if n.fract() == 0.0 => Some(n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(s: &'a str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Json {
+        Json::Number(n)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        Json::Array(items)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        match value {
+            Some(value) => value.into(),
+            None => Json::Null,
+        }
+    }
+}
+
+/// Writes the value as compact JSON, all on one line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(ref s) => write_string(f, s),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// The deepest arrays and objects may be nested. Each level is parsed by a recursive call, so without a limit a
+/// document of nothing but `[`s would overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// The number of arrays and objects the parser is inside.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but the text ended", expected)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().cloned() {
+            Some('n') => self.parse_literal("null", Json::Null),
+            Some('t') => self.parse_literal("true", Json::Bool(true)),
+            Some('f') => self.parse_literal("false", Json::Bool(false)),
+            Some('"') => self.parse_string().map(Json::String),
+            Some('[') | Some('{') if self.depth >= MAX_DEPTH => {
+                Err(format!("arrays and objects nested more than {} deep", MAX_DEPTH))
+            },
+            Some('[') => {
+                self.depth += 1;
+                let array = self.parse_array();
+                self.depth -= 1;
+                array
+            },
+            Some('{') => {
+                self.depth += 1;
+                let object = self.parse_object();
+                self.depth -= 1;
+                object
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("expected a value but the text ended".to_string()),
+        }
+    }
+
+    fn parse_literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+                break;
+            }
+            text.push(c);
+            self.chars.next();
+        }
+        text.parse().map(Json::Number).map_err(|_| format!("invalid number '{}'", text))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.parse_unicode_escape()?,
+                        Some(c) => return Err(format!("invalid escape '\\{}'", c)),
+                        None => return Err("unterminated string".to_string()),
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Parses the digits of a `\u` escape, along with the second half of a surrogate pair if there is one.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let first = self.parse_hex()?;
+        let code = if (0xd800..0xdc00).contains(&first) {
+            self.expect('\\')?;
+            self.expect('u')?;
+            let second = self.parse_hex()?;
+            if !(0xdc00..0xe000).contains(&second) {
+                return Err(format!("invalid surrogate pair {:x} {:x}", first, second));
+            }
+            0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00)
+        } else {
+            first
+        };
+        ::std::char::from_u32(code).ok_or_else(|| format!("invalid character code {:x}", code))
+    }
+
+    fn parse_hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16));
+            match digit {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err("invalid \\u escape".to_string()),
+            }
+        }
+        Ok(code)
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => (),
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err("expected ',' or ']' in array".to_string()),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => (),
+                Some('}') => return Ok(Json::Object(members)),
+                _ => return Err("expected ',' or '}' in object".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_escapes() {
+        let json = Json::parse(r#""a\"b\\c\/d\n\t\r\b\f\u00e9""#).unwrap();
+        assert_eq!(json, Json::String("a\"b\\c/d\n\t\r\u{8}\u{c}\u{e9}".to_string()));
+        assert!(Json::parse(r#""\x""#).is_err());
+        assert!(Json::parse(r#""\u12""#).is_err());
+        assert!(Json::parse(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#).unwrap(), Json::String("\u{1f600}".to_string()));
+        // A high surrogate must be followed by a low one, and a low one can't stand alone.
+        assert!(Json::parse(r#""\ud83d\u0041""#).is_err());
+        assert!(Json::parse(r#""\ud83d""#).is_err());
+        assert!(Json::parse(r#""\ude00""#).is_err());
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(Json::parse("0").unwrap(), Json::Number(0.0));
+        assert_eq!(Json::parse("-12").unwrap().as_i64(), Some(-12));
+        assert_eq!(Json::parse("2.5").unwrap(), Json::Number(2.5));
+        assert_eq!(Json::parse("1e3").unwrap().as_i64(), Some(1000));
+        assert_eq!(Json::parse("2.5").unwrap().as_i64(), None);
+        assert!(Json::parse("-").is_err());
+        assert!(Json::parse("1.2.3").is_err());
+    }
+
+    #[test]
+    fn parses_arrays_and_objects() {
+        let json = Json::parse(r#" { "a" : [1, true, null], "b": {} , "c": [] } "#).unwrap();
+        assert_eq!(json.get("a").and_then(Json::as_array).map(|items| items.len()), Some(3));
+        assert_eq!(json.get("b"), Some(&Json::Object(Vec::new())));
+        assert_eq!(json.get("c"), Some(&Json::Array(Vec::new())));
+        assert_eq!(json.get("d"), None);
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_text() {
+        assert!(Json::parse("[1] x").is_err());
+        assert!(Json::parse("true false").is_err());
+        assert!(Json::parse("null\n").is_ok());
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Json::parse(&"[".repeat(200000)).is_err());
+        assert!(Json::parse(&"{\"a\":".repeat(200000)).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let json = Json::object(vec![
+            ("text", "quote \" slash \\ newline \n control \u{1} emoji \u{1f600}".into()),
+            ("numbers", Json::Array(vec![0.into(), (-3).into(), 2.5.into(), 1e20.into()])),
+            ("flags", Json::Array(vec![true.into(), false.into(), Json::Null])),
+            ("nested", Json::object(vec![("empty", Json::Array(Vec::new()))])),
+        ]);
+        let text = json.to_string();
+        assert!(!text.contains('\n'));
+        assert_eq!(Json::parse(&text).unwrap(), json);
+    }
+}
@@ -0,0 +1,23 @@
+extern crate libc;
+extern crate rand;
+extern crate unicode_width;
+
+pub mod util;
+pub mod ai;
+pub mod board;
+pub mod display;
+pub mod game;
+pub mod grade;
+pub mod input;
+pub mod json;
+pub mod net;
+pub mod path;
+pub mod pc;
+pub mod piece;
+pub mod rules;
+pub mod sim;
+pub mod spectate;
+pub mod tbp;
+pub mod terminal;
+pub mod tune;
+pub mod versus;
@@ -1,541 +1,478 @@
-extern crate libc;
-extern crate rand;
-
-mod util;
-mod display;
-mod terminal;
-
-use display::Display;
+extern crate tetris;
+
+use tetris::ai::{Autoplayer, HeuristicPlanner, Weights, DEFAULT_WEIGHTS};
+use tetris::board::*;
+use tetris::display::Display;
+use tetris::game::{Action, Game};
+use tetris::input::{get_input, Key, KeyMap, KeyState};
+use tetris::piece::{Piece, PieceSet, RotationSystem, MAX_PREVIEWS};
+use tetris::rules::{Finesse, Mode, StackVisibility, FRAMES_PER_SECOND};
+use tetris::tbp::TbpBot;
+use tetris::spectate::{game_state, Broadcaster, Spectator};
+use tetris::terminal;
+use tetris::net::{self, NetBattle};
+use tetris::versus::{Battle, Difficulty, Opponent, Versus, PLAYER_WIDTH};
+use std::cmp;
 use std::thread;
 use std::sync::mpsc;
-use std::time::Duration;
-use util::*;
-
-const BOARD_WIDTH: u32 = 10;
-const BOARD_HEIGHT: u32 = 20;
-const HIDDEN_ROWS: u32 = 2;
-
-enum Key {
-    Up,
-    Down,
-    Left,
-    Right,
-    Space,
-    CtrlC,
-    Char(char),
-}
+use std::time::{Duration, Instant};
 
 enum GameUpdate {
     KeyPress(Key),
     Tick,
 }
 
-#[derive(Debug, Copy, Clone)]
-struct Point {
-    x: i32,
-    y: i32,
-}
-
-struct Board {
-    cells: [[Option<Color>; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
-}
-
-impl Board {
-    pub fn render(&self, display: &mut Display) {
-        for y in HIDDEN_ROWS..BOARD_HEIGHT {
-            display.set_text("|", 0, y, Color::Red, Color::Black);
-            display.set_text("|", BOARD_WIDTH * 2 + 1, y, Color::Red, Color::Black);
-        }
-        for x in 0..(BOARD_WIDTH * 2 + 1) {
-            display.set_text("-", x, BOARD_HEIGHT, Color::Red, Color::Black);
-        }
-        for row in 0..BOARD_HEIGHT {
-            for col in 0..BOARD_WIDTH {
-                match self.cells[row as usize][col as usize] {
-                    Some(color) => {
-                        let c = 1 + (col * 2);
-                        display.set_text(" ", c, row, color, color);
-                        display.set_text(" ", c + 1, row, color, color);
-                    },
-                    None => ()
+/// Starts the threads which send a tick every frame and each key as it's pressed, returning the channel they
+/// send on.
+fn start_event_threads() -> mpsc::Receiver<GameUpdate> {
+    let (tx_event, rx_event) = mpsc::channel();
+
+    // Spawn a thread which sends a game tick every frame
+    {
+        let tx_event = tx_event.clone();
+        thread::spawn(move || {
+            let frame = Duration::new(0, 1_000_000_000 / FRAMES_PER_SECOND);
+            let mut next_frame = Instant::now();
+            loop {
+                next_frame += frame;
+                let now = Instant::now();
+                if next_frame > now {
+                    thread::sleep(next_frame - now);
                 }
-            }
-        }
-    }
-
-    pub fn lock_piece(&mut self, piece: &Piece, origin: Point) {
-        piece.each_point(&mut |row, col| {
-            let x = origin.x + (col as i32);
-            let y = origin.y + (row as i32);
-            self.cells[y as usize][x as usize] = Some(piece.color);
+                tx_event.send(GameUpdate::Tick).unwrap();
+            };
         });
     }
 
-    pub fn collision_test(&self, piece: &Piece, origin: Point) -> bool {
-        let mut found = false;
-        piece.each_point(&mut |row, col| {
-            if !found {
-                let x = origin.x + col;
-                let y = origin.y + row;
-                if x < 0 || x >= (BOARD_WIDTH as i32) || y < 0 || y >= (BOARD_HEIGHT as i32) ||
-                    self.cells[y as usize][x as usize] != None {
-                  found = true;
+    // Spawn a thread which listens for keyboard input
+    {
+        let tx_event = tx_event.clone();
+        thread::spawn(move || {
+            let stdin = &mut std::io::stdin();
+
+            loop {
+                match get_input(stdin) {
+                    Some(k) => tx_event.send(GameUpdate::KeyPress(k)).unwrap(),
+                    None => ()
                 }
             }
         });
-
-        found
     }
 
-    /// Clears the board of any complete lines, shifting down rows to take their place.
-    /// Returns the total number of lines that were cleared.
-    fn clear_lines(&mut self) -> u32 {
-        let mut cleared_lines: usize = 0;
-        for row in (0..self.cells.len()).rev() {
-            if (row as i32) - (cleared_lines as i32) < 0 {
-                break;
-            }
+    rx_event
+}
 
-            if cleared_lines > 0 {
-                self.cells[row] = self.cells[row - cleared_lines];
-                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
-            }
+fn play(game: &mut Game, keymap: &KeyMap, autoplayer: &mut Option<Autoplayer>, broadcaster: Option<&Broadcaster>,
+        display: &mut Display) {
+    let rx_event = start_event_threads();
 
-            while !self.cells[row].iter().any(|x| *x == None) {
-                cleared_lines += 1;
-                self.cells[row] = self.cells[row - cleared_lines];
-                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
+    // Main game loop. The loop listens and responds to timer and keyboard updates received on a channel
+    // as sent by the threads spawned above. The display is only redrawn when something has changed.
+    let mut key_state = KeyState::new();
+    let mut changed = true;
+    loop {
+        if changed {
+            display.clear_buffer();
+            game.render(display);
+            display.render();
+            if let Some(broadcaster) = broadcaster {
+                broadcaster.send(vec![game_state(game)]);
             }
         }
 
-        cleared_lines as u32
-    }
-}
-
-struct Piece {
-    color: Color,
-    shape: Vec<Vec<u8>>,
-}
-
-impl Clone for Piece {
-    fn clone(&self) -> Piece {
-        let mut p = Piece{
-            color: self.color,
-            shape: Vec::with_capacity(self.shape.len())
-        };
-        for row in &self.shape {
-            p.shape.push(row.clone());
+        match rx_event.recv() {
+            Ok(update) => {
+                match update {
+                    GameUpdate::KeyPress(key) => {
+                        let repeat = key_state.press(key, Instant::now());
+                        match keymap.action(key) {
+                            Some(Action::Quit) => break,
+                            Some(action) if action.repeats() || !repeat => { game.press(action, repeat); },
+                            _ => (),
+                        };
+                        changed = true;
+                    },
+                    GameUpdate::Tick => {
+                        let held = key_state.held(Instant::now());
+                        game.set_held_actions(held.into_iter().filter_map(|k| keymap.action(k)).collect());
+                        changed = game.update();
+                        if let Some(ref mut autoplayer) = *autoplayer {
+                            if let Some(action) = autoplayer.update(game) {
+                                changed = game.perform(action) || changed;
+                            }
+                        }
+                    }
+                };
+            },
+            Err(err) => panic!(err)
         }
-        p
     }
 }
 
-impl Piece {
-    pub fn new_o() -> Piece {
-        Piece{
-            color: Color::Cyan,
-            shape: vec![vec![1, 1],
-                        vec![1, 1]]
-        }
-    }
+/// Runs a battle, in which each of the players at the keyboard has their own key bindings.
+fn play_battle<B: Battle>(battle: &mut B, keymaps: &[KeyMap], broadcaster: Option<&Broadcaster>,
+                          display: &mut Display) {
+    let rx_event = start_event_threads();
 
-    pub fn new_l() -> Piece {
-        Piece{
-            color: Color::Orange,
-            shape: vec![vec![0, 0, 1],
-                        vec![1, 1, 1],
-                        vec![0, 0, 0]]
-        }
-    }
-
-    pub fn new_j() -> Piece {
-        Piece{
-            color: Color::Blue,
-            shape: vec![vec![1, 0, 0],
-                        vec![1, 1, 1],
-                        vec![0, 0, 0]]
-        }
-    }
-
-    pub fn new_t() -> Piece {
-        Piece{
-            color: Color::Purple,
-            shape: vec![vec![0, 1, 0],
-                        vec![1, 1, 1],
-                        vec![0, 0, 0]]
-        }
-    }
-
-    pub fn new_s() -> Piece {
-        Piece{
-            color: Color::Green,
-            shape: vec![vec![0, 1, 1],
-                        vec![1, 1, 0],
-                        vec![0, 0, 0]]
-        }
-    }
-
-    pub fn new_z() -> Piece {
-        Piece{
-            color: Color::Red,
-            shape: vec![vec![1, 1, 0],
-                        vec![0, 1, 1],
-                        vec![0, 0, 0]]
-        }
-    }
-
-    pub fn new_i() -> Piece {
-        Piece{
-            color: Color::Cyan,
-            shape: vec![vec![0, 0, 0, 0],
-                        vec![1, 1, 1, 1],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0]]
+    let mut key_state = KeyState::new();
+    let mut changed = true;
+    loop {
+        if changed {
+            display.clear_buffer();
+            battle.render(display);
+            display.render();
+            if let Some(broadcaster) = broadcaster {
+                broadcaster.send(battle.spectator_states());
+            }
         }
-    }
-
-    fn rotate(&mut self, direction: Direction) {
-        let size = self.shape.len();
 
-        for row in 0..size/2 {
-            for col in row..(size - row - 1) {
-                let t = self.shape[row][col];
-
-                match direction {
-                    Direction::Left => {
-                        self.shape[row][col] = self.shape[col][size - row - 1];
-                        self.shape[col][size - row - 1] = self.shape[size - row - 1][size - col - 1];
-                        self.shape[size - row - 1][size - col - 1] = self.shape[size - col - 1][row];
-                        self.shape[size - col - 1][row] = t;
-                    },
-                    Direction::Right => {
-                        self.shape[row][col] = self.shape[size - col - 1][row];
-                        self.shape[size - col - 1][row] = self.shape[size - row - 1][size - col - 1];
-                        self.shape[size - row - 1][size - col - 1] = self.shape[col][size - row - 1];
-                        self.shape[col][size - row - 1] = t;
+        match rx_event.recv() {
+            Ok(GameUpdate::KeyPress(key)) => {
+                let repeat = key_state.press(key, Instant::now());
+                for (player, keymap) in keymaps.iter().enumerate() {
+                    match keymap.action(key) {
+                        Some(Action::Quit) => return,
+                        Some(action) if action.repeats() || !repeat => { battle.press(player, action, repeat); },
+                        _ => (),
                     }
                 }
-            }
-        }
-    }
-
-    fn each_point(&self, callback: &mut FnMut(i32, i32)) {
-        let piece_width = self.shape.len() as i32;
-        for row in 0..piece_width {
-            for col in 0..piece_width {
-                if self.shape[row as usize][col as usize] != 0 {
-                    callback(row, col);
+                changed = true;
+            },
+            Ok(GameUpdate::Tick) => {
+                let held = key_state.held(Instant::now());
+                for (player, keymap) in keymaps.iter().enumerate() {
+                    let actions = held.iter().filter_map(|&k| keymap.action(k)).collect();
+                    battle.set_held_actions(player, actions);
                 }
-            }
+                changed = battle.update();
+            },
+            Err(err) => panic!(err)
         }
     }
 }
 
-/// Implements a queue of randomized tetrominoes.
-///
-/// Instead of a purely random stream of tetromino types, this queue generates a random ordering of all
-/// possible types and ensures all of those pieces are used before re-generating a new random set. This helps
-/// avoid pathological cases where purely random generation provides the same piece type repeately in a row,
-/// or fails to provide a required piece for a very long time.
-struct PieceBag {
-    pieces: Vec<Piece>
-}
+/// Watches a game being broadcast elsewhere until the quit key is pressed.
+fn spectate(spectator: &mut Spectator, keymap: &KeyMap, display: &mut Display) {
+    let rx_event = start_event_threads();
 
-impl PieceBag {
-    fn new() -> PieceBag {
-        let mut p = PieceBag{
-            pieces: Vec::new()
-        };
-        p.fill_bag();
-        p
-    }
-
-    /// Removes and returns the next piece in the queue.
-    fn pop(&mut self) -> Piece {
-        let piece = self.pieces.remove(0);
-        if self.pieces.is_empty() {
-            self.fill_bag();
-        }
-        piece
-    }
-
-    /// Returns a copy of the next piece in the queue.
-    fn peek(&self) -> Piece {
-        match self.pieces.first() {
-            Some(p) => p.clone(),
-            None => panic!("No next piece in piece bag")
+    let mut changed = true;
+    loop {
+        if changed {
+            display.clear_buffer();
+            spectator.render(display);
+            display.render();
         }
-    }
-
-    /// Generates a random ordering of all possible pieces and adds them to the piece queue.
-    fn fill_bag(&mut self) {
-        use rand::Rng;
-
-        let mut pieces: Vec<Piece> = vec![
-            Piece::new_o(),
-            Piece::new_l(),
-            Piece::new_j(),
-            Piece::new_t(),
-            Piece::new_s(),
-            Piece::new_z(),
-            Piece::new_i()
-        ];
 
-        let mut rng = rand::thread_rng();
-        while !pieces.is_empty() {
-            let i = rng.gen::<usize>() % pieces.len();
-            self.pieces.push(pieces.swap_remove(i));
+        match rx_event.recv() {
+            Ok(GameUpdate::KeyPress(key)) => {
+                if keymap.action(key) == Some(Action::Quit) {
+                    return;
+                }
+                changed = false;
+            },
+            Ok(GameUpdate::Tick) => changed = spectator.update(),
+            Err(err) => panic!(err)
         }
     }
 }
 
-struct Game {
-    board: Board,
-    piece_bag: PieceBag,
-    piece: Piece,
-    piece_position: Point,
+fn exit_with_error(err: String) -> ! {
+    println!("{}", err);
+    std::process::exit(1);
 }
 
-impl Game {
-    fn new() -> Game {
-        let mut piece_bag = PieceBag::new();
-        let piece = piece_bag.pop();
+fn usage() -> ! {
+    println!("Usage: tetris [--mode <mode>] [--level <n>] [--pieces <set>] [--rotation <system>]");
+    println!("              [--stack <visibility>] [--big] [--previews <n>] [--are <frames>]");
+    println!("              [--line-clear-delay <frames>]");
+    println!("              [--finesse <check>] [--autoplay] [--autoplay-speed <n>] [--weights <file>]");
+    println!("              [--bot <command>] [--bind <action>=<key>]... [--broadcast <port>]");
+    println!("       tetris --versus [--bind <action>=<key>]... [--bind2 <action>=<key>]... [<game options>]");
+    println!("       tetris --versus-ai <difficulty> [--weights <file>] [--bind <action>=<key>]...");
+    println!("                         [<game options>]");
+    println!("       tetris --host <port> [--bind <action>=<key>]... [<game options>]");
+    println!("       tetris --connect <host>:<port> [--bind <action>=<key>]... [--finesse <check>]");
+    println!("       tetris --spectate <host>:<port>");
+    println!();
+    println!("Options:");
+    println!("    --mode <mode>         Game mode: marathon (default), nes, master or zen");
+    println!("    --level <n>           Level to start at");
+    println!("    --pieces <set>        Piece set to play with: one of {}, or the path of a piece set file",
+             PieceSet::builtin_names().join(", "));
+    println!("    --rotation <system>   Rotation system for the tetrominoes: srs or ars. Can't be combined");
+    println!("                          with --pieces");
+    println!("    --stack <visibility>  How much of the stack is shown: visible (default), fading or invisible");
+    println!("    --big                 Play with pieces at double size");
+    println!("    --previews <n>        Number of upcoming pieces shown, up to {} (default 5, or 1 in nes and",
+             MAX_PREVIEWS);
+    println!("                          master)");
+    println!("    --are <frames>        Entry delay between a piece locking and the next appearing");
+    println!("    --line-clear-delay <frames>");
+    println!("                          Delay while completed lines are cleared");
+    println!("    --finesse <check>     Check each piece was placed with as few key presses as possible: off");
+    println!("                          (default), count, or practice to send faulty pieces back to the top");
+    println!("    --autoplay            Let the AI play the game");
+    println!("    --autoplay-speed <n>  Number of moves the AI or bot makes a second (default 10)");
+    println!("    --weights <file>      JSON file of weights for the AI and hints, such as those found by");
+    println!("                          tetris-sim tune");
+    println!("    --bot <command>       Let a bot speaking the Tetris Bot Protocol play the game");
+    println!("    --bind <action>=<key> Bind a key to an action. Actions are left, right, soft-drop, hard-drop,");
+    println!("                          rotate-ccw, rotate-cw, rotate-180, hold, undo, redo, hint,");
+    println!("                          pc-finder and quit.");
+    println!("                          Keys are up, down, left, right, space or a single character.");
+    println!("    --versus              Play a battle against a second player on the same keyboard, who plays");
+    println!("                          with the arrow keys while the first uses W, A, S and D. In a battle");
+    println!("                          --bind rebinds the first player's keys and --bind2 the second's.");
+    println!("    --versus-ai <difficulty>");
+    println!("                          Play a battle against the computer: easy, medium, hard or expert");
+    println!("    --host <port>         Host a battle against a player on another machine, waiting for them to");
+    println!("                          connect on the port. The host's game options are used for both.");
+    println!("    --connect <host>:<port>");
+    println!("                          Join a battle hosted by another player");
+    println!("    --broadcast <port>    Stream the game, or both sides of a battle, to spectators connecting to");
+    println!("                          the port on this machine");
+    println!("    --spectate <host>:<port>");
+    println!("                          Watch a game being streamed with --broadcast");
+    std::process::exit(1);
+}
 
-        let mut game = Game {
-            board: Board{
-                cells: [[None; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize]
+fn main() {
+    let mut mode = Mode::Marathon;
+    let mut level = None;
+    let mut piece_set = None;
+    let mut rotation = None;
+    let mut stack = None;
+    let mut big = false;
+    let mut previews = None;
+    let mut are = None;
+    let mut line_clear_delay = None;
+    let mut finesse = None;
+    let mut keymap = KeyMap::new();
+    let mut autoplay = false;
+    let mut autoplay_speed = 10;
+    let mut weights = DEFAULT_WEIGHTS;
+    let mut bot = None;
+    let mut versus = false;
+    let mut difficulty = None;
+    let mut keymaps = vec![KeyMap::for_player(0), KeyMap::for_player(1)];
+    let mut host = None;
+    let mut connect = None;
+    let mut broadcast_port = None;
+    let mut spectate_address = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                mode = Mode::from_name(&name).unwrap_or_else(|| usage());
             },
-            piece_bag: piece_bag,
-            piece: piece,
-            piece_position: Point{ x: 0, y: 0 }
-        };
-
-        game.place_new_piece();
-        game
+            "--level" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                level = Some(n.parse().unwrap_or_else(|_| usage()));
+            },
+            "--pieces" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                piece_set = match PieceSet::find(&name) {
+                    Ok(set) => Some(set),
+                    Err(err) => {
+                        println!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+            },
+            "--rotation" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                rotation = Some(RotationSystem::from_name(&name).unwrap_or_else(|| usage()));
+            },
+            "--stack" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                stack = Some(StackVisibility::from_name(&name).unwrap_or_else(|| usage()));
+            },
+            "--big" => big = true,
+            "--previews" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                previews = match n.parse() {
+                    Ok(n) if n <= MAX_PREVIEWS => Some(n),
+                    _ => usage(),
+                };
+            },
+            "--are" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                are = Some(n.parse().unwrap_or_else(|_| usage()));
+            },
+            "--line-clear-delay" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                line_clear_delay = Some(n.parse().unwrap_or_else(|_| usage()));
+            },
+            "--finesse" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                finesse = Some(Finesse::from_name(&name).unwrap_or_else(|| usage()));
+            },
+            "--autoplay" => autoplay = true,
+            "--autoplay-speed" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                autoplay_speed = n.parse().unwrap_or_else(|_| usage());
+            },
+            "--weights" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                weights = match Weights::load(&path) {
+                    Ok(weights) => weights,
+                    Err(err) => {
+                        println!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+            },
+            "--bot" => bot = Some(args.next().unwrap_or_else(|| usage())),
+            "--bind" => {
+                let binding = args.next().unwrap_or_else(|| usage());
+                if !keymap.bind_str(&binding) || !keymaps[0].bind_str(&binding) {
+                    usage();
+                }
+            },
+            "--bind2" => {
+                let binding = args.next().unwrap_or_else(|| usage());
+                if !keymaps[1].bind_str(&binding) {
+                    usage();
+                }
+            },
+            "--versus" => versus = true,
+            "--versus-ai" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                difficulty = Some((Difficulty::from_name(&name).unwrap_or_else(|| usage()), name));
+            },
+            "--host" => {
+                let port = args.next().unwrap_or_else(|| usage());
+                host = Some(port.parse::<u16>().unwrap_or_else(|_| usage()));
+            },
+            "--connect" => connect = Some(args.next().unwrap_or_else(|| usage())),
+            "--broadcast" => {
+                let port = args.next().unwrap_or_else(|| usage());
+                broadcast_port = Some(port.parse::<u16>().unwrap_or_else(|_| usage()));
+            },
+            "--spectate" => spectate_address = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
     }
 
-    /// Returns the new position of the current piece if it were to be dropped.
-    fn find_dropped_position(&self) -> Point {
-        let mut origin = self.piece_position;
-        while !self.board.collision_test(&self.piece, origin) {
-            origin.y += 1;
+    // A rotation system chooses a set of tetrominoes, so it would override any other set asked for.
+    if let Some(system) = rotation {
+        if piece_set.is_some() {
+            println!("--rotation can't be combined with --pieces");
+            std::process::exit(1);
         }
-        origin.y -= 1;
-        origin
+        piece_set = Some(system.piece_set());
     }
 
-    /// Draws the game to the display.
-    fn render(&self, display: &mut Display) {
-        // Render the board
-        self.board.render(display);
-
-        // Render the level
-        let left_margin = BOARD_WIDTH * 2 + 5;
-        display.set_text("Level: 1", left_margin, 3, Color::Red, Color::Black);
-
-        // Render the currently falling piece
-        let x = 1 + (2 * self.piece_position.x);
-        self.render_piece(display, &self.piece, Point{ x: x, y: self.piece_position.y });
-
-        // Render a ghost piece
-        let ghost_position = self.find_dropped_position();
-        self.render_piece(display, &self.piece, Point{ x: x, y: ghost_position.y });
-
-        // Render the next piece
-        display.set_text("Next piece:", left_margin, 7, Color::Red, Color::Black);
-        let next_piece = self.piece_bag.peek();
-        self.render_piece(display, &next_piece, Point{ x: (left_margin as i32) + 2, y: 9 });
+    let mut rules = mode.rules();
+    if let Some(level) = level {
+        rules.start_level = level;
     }
-
-    fn render_piece(&self, display: &mut Display, piece: &Piece, origin: Point) {
-        let color = piece.color;
-
-        piece.each_point(&mut |row, col| {
-            let x = (origin.x + 2 * col) as u32;
-            let y = (origin.y + row) as u32;
-            display.set_text(" ", x, y, color, color);
-            display.set_text(" ", x + 1, y, color, color);
-        });
+    if let Some(piece_set) = piece_set {
+        rules.piece_set = piece_set;
     }
-
-    /// Moves the current piece in the specified direction. Returns true if the piece could be moved and
-    /// didn't collide.
-    fn move_piece(&mut self, x: i32, y: i32) -> bool {
-        let new_position = Point{
-            x: self.piece_position.x + x,
-            y: self.piece_position.y + y,
-        };
-        if self.board.collision_test(&self.piece, new_position) {
-            false
-        } else {
-            self.piece_position = new_position;
-            true
-        }
+    if let Some(stack) = stack {
+        rules.stack = stack;
     }
-
-    /// Rotates the current piece in the specified direction. Returns true if the piece could be rotated
-    /// without any collisions.
-    fn rotate_piece(&mut self, direction: Direction) -> bool {
-        let mut new_piece = self.piece.clone();
-        new_piece.rotate(direction);
-
-        if self.board.collision_test(&new_piece, self.piece_position) {
-            false
-        } else {
-            self.piece = new_piece;
-            true
-        }
+    rules.big = big;
+    let too_wide = |def| Piece::new(def).with_scale(2).size() > BOARD_WIDTH as usize;
+    if big && rules.piece_set.pieces.iter().any(too_wide) {
+        println!("The pieces in this set are too wide to play big");
+        std::process::exit(1);
     }
-
-    /// Positions the current piece at the top of the board. Returns true if the piece can be placed without
-    /// any collisions.
-    fn place_new_piece(&mut self) -> bool {
-        let origin = Point{
-            x: ((BOARD_WIDTH - (self.piece.shape.len() as u32)) / 2) as i32,
-            y: 0,
-        };
-        if self.board.collision_test(&self.piece, origin) {
-            false
-        } else {
-            self.piece_position = origin;
-            true
-        }
+    if let Some(previews) = previews {
+        rules.previews = previews;
     }
-
-    /// Advances the game by moving the current piece down one step. If the piece cannot move down, the piece
-    /// is locked and the game is set up to drop the next piece.  Returns true if the game could be advanced,
-    /// false if the player has lost.
-    fn advance_game(&mut self) -> bool {
-        if !self.move_piece(0, 1) {
-            self.board.lock_piece(&self.piece, self.piece_position);
-            self.board.clear_lines();
-            self.piece = self.piece_bag.pop();
-
-            if !self.place_new_piece() {
-                return false;
-            }
-        }
-
-        true
+    if are.is_some() {
+        rules.are = are;
     }
-
-    /// Drops the current piece to the lowest spot on the board where it fits without collisions and
-    /// advances the game.
-    fn drop_piece(&mut self) -> bool {
-        while self.move_piece(0, 1) {}
-        self.advance_game()
+    if line_clear_delay.is_some() {
+        rules.line_clear_delay = line_clear_delay;
     }
-
-    fn keypress(&mut self, key: Key) {
-        match key {
-            Key::Left => self.move_piece(-1, 0),
-            Key::Right => self.move_piece(1, 0),
-            Key::Down => self.advance_game(),
-            Key::Up => self.rotate_piece(Direction::Left),
-            Key::Space => self.drop_piece(),
-            Key::Char('q') => self.rotate_piece(Direction::Left),
-            Key::Char('e') => self.rotate_piece(Direction::Right),
-            _ => false,
-        };
+    if let Some(finesse) = finesse {
+        rules.finesse = finesse;
     }
 
+    let height = BOARD_HEIGHT - HIDDEN_ROWS + SPAWN_ROWS + 1;
+    if let Some(address) = spectate_address {
+        let spectator = &mut Spectator::connect(&address).unwrap_or_else(|err| exit_with_error(err));
+        let display = &mut Display::new(BOARD_WIDTH * 2 + 100, height);
+        let _restorer = terminal::set_terminal_raw_mode();
+        spectate(spectator, &keymap, display);
+        return;
+    }
+    let broadcaster = broadcast_port.map(|port| {
+        Broadcaster::listen(port).unwrap_or_else(|err| exit_with_error(err))
+    });
 
-    fn play(&mut self, display: &mut Display) {
-        let (tx_event, rx_event) = mpsc::channel();
-
-        // Spawn a thread which sends periodic game ticks to advance the piece
-        {
-            let tx_event = tx_event.clone();
-            thread::spawn(move || {
-                loop {
-                    thread::sleep(Duration::from_millis(500));
-                    tx_event.send(GameUpdate::Tick).unwrap();
-                };
-            });
+    if versus || difficulty.is_some() {
+        if autoplay || bot.is_some() || (versus && difficulty.is_some()) {
+            usage();
         }
-
-        // Spawn a thread which listens for keyboard input
-        {
-            let tx_event = tx_event.clone();
-            thread::spawn(move || {
-                let stdin = &mut std::io::stdin();
-
-                loop {
-                    match get_input(stdin) {
-                        Some(k) => tx_event.send(GameUpdate::KeyPress(k)).unwrap(),
-                        None => ()
-                    }
-                }
-            });
+        // Each player's half of the screen only has room for three pieces of preview.
+        rules.previews = cmp::min(rules.previews, 3);
+        let display = &mut Display::new(PLAYER_WIDTH * 2, height);
+        let battle = &mut Versus::new(rules);
+        if let Some((difficulty, name)) = difficulty {
+            // Against the computer the player keeps the usual keys.
+            keymaps = vec![keymap];
+            battle.players[0].name = "You".to_string();
+            battle.players[1].name = format!("Computer ({})", name);
+            let planner = Opponent::new(difficulty, weights);
+            battle.set_opponent(Autoplayer::new(Box::new(planner), FRAMES_PER_SECOND));
         }
+        let _restorer = terminal::set_terminal_raw_mode();
+        play_battle(battle, &keymaps, broadcaster.as_ref(), display);
+        return;
+    }
 
-        // Main game loop. The loop listens and responds to timer and keyboard updates received on a channel
-        // as sent by the threads spawned above.
-        loop {
-            display.clear_buffer();
-            self.render(display);
-            display.render();
-
-            match rx_event.recv() {
-                Ok(update) => {
-                    match update {
-                        GameUpdate::KeyPress(key) => {
-                            match key {
-                                Key::Char('z') | Key::CtrlC => break,
-                                k => { self.keypress(k); }
-                            };
-                        },
-                        GameUpdate::Tick => { self.advance_game(); }
-                    };
-                },
-                Err(err) => panic!(err)
-            }
+    if host.is_some() || connect.is_some() {
+        if autoplay || bot.is_some() || (host.is_some() && connect.is_some()) {
+            usage();
         }
+        let battle = &mut match (host, connect) {
+            (Some(port), _) => {
+                rules.previews = cmp::min(rules.previews, 3);
+                println!("Waiting for the other player to connect on port {}...", port);
+                let (connection, seed) = net::host(port, &rules).unwrap_or_else(|err| exit_with_error(err));
+                NetBattle::new(rules, seed, connection)
+            },
+            (_, Some(address)) => {
+                let (connection, mut host_rules, seed) = net::connect(&address)
+                    .unwrap_or_else(|err| exit_with_error(err));
+                // How strictly finesse is checked is up to each player.
+                host_rules.finesse = rules.finesse;
+                host_rules.previews = cmp::min(host_rules.previews, 3);
+                NetBattle::new(host_rules, seed, connection)
+            },
+            _ => unreachable!(),
+        };
+        let display = &mut Display::new(PLAYER_WIDTH * 2, height);
+        let _restorer = terminal::set_terminal_raw_mode();
+        play_battle(battle, &[keymap], broadcaster.as_ref(), display);
+        return;
     }
-}
 
-fn get_input(stdin: &mut std::io::Stdin) -> Option<Key> {
-    use std::io::Read;
+    let display = &mut Display::new(BOARD_WIDTH * 2 + 100, height);
+    let game = &mut Game::new(rules);
+    game.set_hint_weights(weights);
 
-    let c = &mut [0u8];
-    match stdin.read(c) {
-        Ok(_) => {
-            match std::str::from_utf8(c) {
-                Ok("w") => Some(Key::Up),
-                Ok("a") => Some(Key::Left),
-                Ok("s") => Some(Key::Down),
-                Ok("d") => Some(Key::Right),
-                Ok(" ") => Some(Key::Space),
-                Ok("\x03") => Some(Key::CtrlC),
-                // Escape sequence started - must read two more bytes.
-                Ok("\x1b") => {
-                    let code = &mut [0u8; 2];
-                    match stdin.read(code) {
-                        Ok(_) => {
-                            match std::str::from_utf8(code) {
-                                Ok("[A") => Some(Key::Up),
-                                Ok("[B") => Some(Key::Down),
-                                Ok("[C") => Some(Key::Right),
-                                Ok("[D") => Some(Key::Left),
-                                _ => None
-                            }
-                        },
-                        Err(msg) => panic!(format!("could not read from standard in: {}", msg))
-                    }
-                },
-                Ok(n) => Some(Key::Char(n.chars().next().unwrap())),
-                _ => None
-            }
+    let autoplayer = &mut match bot {
+        Some(command) => match TbpBot::start(&command, game) {
+            Ok(bot) => Some(Autoplayer::new(Box::new(bot), autoplay_speed)),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            },
         },
-        Err(msg) => panic!(format!("could not read from standard in: {}", msg))
-    }
-}
-
-fn main() {
-    let display = &mut Display::new(BOARD_WIDTH * 2 + 100, BOARD_HEIGHT + 2);
-    let game = &mut Game::new();
+        None if autoplay => {
+            Some(Autoplayer::new(Box::new(HeuristicPlanner::new(weights)), autoplay_speed))
+        },
+        None => None,
+    };
 
     let _restorer = terminal::set_terminal_raw_mode();
 
-    game.play(display);
+    play(game, &keymap, autoplayer, broadcaster.as_ref(), display);
 }
@@ -0,0 +1,518 @@
+use board::*;
+use display::Display;
+use game::{Action, Game};
+use json::Json;
+use piece::{Piece, PieceSet};
+use rules::{Mode, Rules, StackVisibility, FRAMES_PER_SECOND};
+use std::cmp;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use util::*;
+use spectate::player_state;
+use versus::{render_status, Battle, Player, PLAYER_WIDTH};
+
+/// Changed whenever the messages do, so that different versions of the game refuse to play each other.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How long to wait for the other player to answer while connecting.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// How long a message may take to send before the other player is taken to have gone.
+const WRITE_TIMEOUT_SECS: u64 = 5;
+
+/// The number of frames between pings measuring the latency.
+const PING_INTERVAL: u32 = FRAMES_PER_SECOND;
+
+/// The longest line of JSON read from another machine. The largest message, a game's state, is well under this.
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+
+/// The letters which stand for each color in the boards sent to the other player. Empty cells are sent as `.`.
+const COLOR_CODES: [(char, Color); 13] = [
+    ('c', Color::Cyan), ('p', Color::Purple), ('g', Color::Green), ('r', Color::Red), ('b', Color::Blue),
+    ('o', Color::Orange), ('x', Color::Gray), ('y', Color::Yellow), ('m', Color::Magenta), ('t', Color::Teal),
+    ('w', Color::White), ('k', Color::Pink), ('n', Color::Brown),
+];
+
+/// Returns the letter standing for a color.
+pub fn color_code(color: Color) -> char {
+    COLOR_CODES.iter().find(|&&(_, c)| c == color).map_or('x', |&(code, _)| code)
+}
+
+pub fn color_from_code(code: char) -> Option<Color> {
+    COLOR_CODES.iter().find(|&&(c, _)| c == code).map(|&(_, color)| color)
+}
+
+/// Describes the cells of a board as a string for each row, from the top down, with a letter for the color
+/// of each block.
+pub fn board_to_json(board: &Board) -> Json {
+    let rows: Vec<Json> = board.cells.iter()
+        .map(|row| row.iter().map(|cell| cell.map_or('.', color_code)).collect::<String>().into())
+        .collect();
+    rows.into()
+}
+
+/// Fills in a board's cells from rows described by `board_to_json`.
+pub fn read_board(json: &Json, board: &mut Board) {
+    let rows = json.as_array().unwrap_or(&[]);
+    for (y, row) in rows.iter().take(BOARD_HEIGHT as usize).enumerate() {
+        for (x, code) in row.as_str().unwrap_or("").chars().take(BOARD_WIDTH as usize).enumerate() {
+            board.cells[y][x] = color_from_code(code);
+        }
+    }
+}
+
+/// Starts a thread reading lines of JSON from another machine, returning the channel the messages it reads
+/// are sent on. Lines which can't be understood are skipped, while a line longer than any message could be
+/// ends the reading, as nothing more from the other end can be trusted. The channel hangs up when reading ends.
+pub fn read_messages<R: Read + Send + 'static>(reader: R) -> Receiver<Json> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.by_ref().take(MAX_LINE_LENGTH).read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+            if line.last() != Some(&b'\n') {
+                break;
+            }
+            let message = String::from_utf8(line.clone()).map_err(|err| err.to_string())
+                .and_then(|text| Json::parse(&text));
+            if let Ok(message) = message {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// A connection to the other player, exchanging messages as lines of JSON. Messages are read and written on
+/// their own threads, so that neither waiting for messages nor a slow network ever holds up the game.
+pub struct Connection {
+    stream: TcpStream,
+    messages: Receiver<Json>,
+    /// The channel messages are queued on to be written, and the thread writing them. The thread stops, hanging
+    /// up the channel, if a write fails or takes longer than `WRITE_TIMEOUT_SECS`.
+    outgoing: Option<Sender<String>>,
+    writer: Option<JoinHandle<()>>,
+    /// Set once the other player has hung up or the connection has failed.
+    closed: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Connection, String> {
+        let _ = stream.set_nodelay(true);
+        let reader = stream.try_clone().map_err(|err| format!("Couldn't read from the connection: {}", err))?;
+        let mut writer = stream.try_clone().map_err(|err| format!("Couldn't write to the connection: {}", err))?;
+        writer.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)))
+            .map_err(|err| format!("Couldn't write to the connection: {}", err))?;
+        let (tx, rx) = mpsc::channel::<String>();
+        let writer = thread::spawn(move || {
+            for line in rx {
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Connection{
+            stream,
+            messages: read_messages(reader),
+            outgoing: Some(tx),
+            writer: Some(writer),
+            closed: false,
+        })
+    }
+
+    /// Queues a message to be sent, marking the connection closed if the writing thread has given up.
+    fn send(&mut self, message: &Json) {
+        let sent = self.outgoing.as_ref().is_some_and(|tx| tx.send(message.to_string()).is_ok());
+        if !sent {
+            self.closed = true;
+        }
+    }
+
+    /// Returns the next message, if one has arrived.
+    fn try_recv(&mut self) -> Option<Json> {
+        match self.messages.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.closed = true;
+                None
+            },
+        }
+    }
+
+    /// Waits for the next message while connecting, which must be of the given type.
+    fn expect(&mut self, kind: &str) -> Result<Json, String> {
+        let message = match self.messages.recv_timeout(Duration::from_secs(HANDSHAKE_TIMEOUT_SECS)) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => return Err("Timed out waiting for the other player".to_string()),
+            Err(RecvTimeoutError::Disconnected) => return Err("The other player hung up".to_string()),
+        };
+        if message.get("type").and_then(Json::as_str) != Some(kind) {
+            return Err(format!("Expected a '{}' message from the other player, but got {}", kind, message));
+        }
+        match message.get("version").and_then(Json::as_i64) {
+            Some(version) if version == PROTOCOL_VERSION as i64 => Ok(message),
+            _ => Err("The other player is running a different version of the game".to_string()),
+        }
+    }
+}
+
+impl Drop for Connection {
+    /// Lets the writing thread finish sending what's queued, such as news that the game is over, before
+    /// hanging up.
+    fn drop(&mut self) {
+        self.outgoing = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Waits on `port` for the other player to connect, then tells them the rules of the game and the seed its
+/// pieces are dealt from. Returns the connection and the seed.
+pub fn host(port: u16, rules: &Rules) -> Result<(Connection, u64), String> {
+    if PieceSet::builtin(&rules.piece_set.name).is_none() {
+        return Err("Network games can only be played with the built-in piece sets".to_string());
+    }
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .map_err(|err| format!("Couldn't listen on port {}: {}", port, err))?;
+    let (stream, _) = listener.accept().map_err(|err| format!("Couldn't accept a connection: {}", err))?;
+    let mut connection = Connection::new(stream)?;
+    connection.expect("hello")?;
+
+    // The seed is kept to 32 bits so that it survives being sent as a JSON number.
+    let seed = ::rand::random::<u32>() as u64;
+    connection.send(&Json::object(vec![
+        ("type", "start".into()),
+        ("version", PROTOCOL_VERSION.into()),
+        ("seed", (seed as f64).into()),
+        ("rules", rules_to_json(rules)),
+    ]));
+    Ok((connection, seed))
+}
+
+/// Connects to the player hosting a game at `address`, given as `<host>:<port>`. Returns the connection, and
+/// the rules and seed the host chose.
+pub fn connect(address: &str) -> Result<(Connection, Rules, u64), String> {
+    let stream = TcpStream::connect(address).map_err(|err| format!("Couldn't connect to {}: {}", address, err))?;
+    let mut connection = Connection::new(stream)?;
+    connection.send(&Json::object(vec![("type", "hello".into()), ("version", PROTOCOL_VERSION.into())]));
+    let start = connection.expect("start")?;
+    let rules = rules_from_json(start.get("rules").unwrap_or(&Json::Null))?;
+    let seed = start.get("seed").and_then(Json::as_i64).ok_or("The host didn't send a seed")? as u64;
+    Ok((connection, rules, seed))
+}
+
+/// Describes the rules the host has chosen, for the other player to play by. Everything else about the rules
+/// follows from the mode.
+fn rules_to_json(rules: &Rules) -> Json {
+    let (stack, fade_frames) = match rules.stack {
+        StackVisibility::Visible => ("visible", None),
+        StackVisibility::Fading(frames) => ("fading", Some(frames)),
+        StackVisibility::Invisible => ("invisible", None),
+    };
+    Json::object(vec![
+        ("mode", rules.mode.name().to_lowercase().into()),
+        ("pieces", rules.piece_set.name.as_str().into()),
+        ("level", rules.start_level.into()),
+        ("previews", (rules.previews as u32).into()),
+        ("big", rules.big.into()),
+        ("stack", stack.into()),
+        ("fade_frames", fade_frames.into()),
+        ("are", rules.are.into()),
+        ("line_clear_delay", rules.line_clear_delay.into()),
+    ])
+}
+
+fn rules_from_json(json: &Json) -> Result<Rules, String> {
+    let text = |key: &str| json.get(key).and_then(Json::as_str).unwrap_or("");
+    let number = |key: &str| json.get(key).and_then(Json::as_i64).map(|n| n as u32);
+
+    let mode = Mode::from_name(text("mode"))
+        .ok_or_else(|| format!("The host chose an unknown mode '{}'", text("mode")))?;
+    let mut rules = mode.rules();
+    rules.piece_set = PieceSet::builtin(text("pieces"))
+        .ok_or_else(|| format!("The host chose an unknown piece set '{}'", text("pieces")))?;
+    rules.start_level = number("level").unwrap_or(rules.start_level);
+    rules.previews = number("previews").map_or(rules.previews, |n| n as usize);
+    rules.big = json.get("big") == Some(&Json::Bool(true));
+    rules.stack = match text("stack") {
+        "fading" => StackVisibility::Fading(number("fade_frames").unwrap_or(5 * FRAMES_PER_SECOND)),
+        "invisible" => StackVisibility::Invisible,
+        _ => StackVisibility::Visible,
+    };
+    rules.are = number("are");
+    rules.line_clear_delay = number("line_clear_delay");
+    Ok(rules)
+}
+
+/// What's known of the other player's game, from the last state they sent.
+struct Remote {
+    board: Board,
+    score: u32,
+    lines: u32,
+    hold: Option<String>,
+    queue: Vec<String>,
+    level: u32,
+    incoming: u32,
+    /// Why their game ended, once it has.
+    game_over: Option<String>,
+}
+
+/// A battle against a player on another machine. Each side plays its own game, dealt the same pieces, and
+/// after every piece tells the other the board it left and the garbage any clear sent.
+pub struct NetBattle {
+    player: Player,
+    opponent: Remote,
+    connection: Connection,
+    /// The pieces of the set being played, for drawing the opponent's preview.
+    pieces: PieceSet,
+    /// When the battle started, which pings are timed from, and the round trip time last measured.
+    started: Instant,
+    latency: Option<u32>,
+    frames: u32,
+    /// The number of pieces the game had locked when the state was last sent, and whether the other player
+    /// has been told the game is over.
+    sent_pieces: u32,
+    sent_game_over: bool,
+}
+
+impl NetBattle {
+    pub fn new(rules: Rules, seed: u64, connection: Connection) -> NetBattle {
+        let pieces = rules.piece_set.clone();
+        NetBattle{
+            player: Player::new("You", Game::with_seed(rules, seed)),
+            opponent: Remote{
+                board: Board::new(),
+                score: 0,
+                lines: 0,
+                hold: None,
+                queue: Vec::new(),
+                level: 0,
+                incoming: 0,
+                game_over: None,
+            },
+            connection,
+            pieces,
+            started: Instant::now(),
+            latency: None,
+            frames: 0,
+            sent_pieces: 0,
+            sent_game_over: false,
+        }
+    }
+
+    /// Returns true once either game has ended or the connection has been lost.
+    pub fn is_over(&self) -> bool {
+        self.player.game.game_over().is_some() || self.opponent.game_over.is_some() || self.connection.closed
+    }
+
+    fn millis(&self) -> f64 {
+        let elapsed = self.started.elapsed();
+        elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1_000_000.0
+    }
+
+    /// Tells the other player about any attack made by a piece which has just locked, and about the board it
+    /// left.
+    fn send_updates(&mut self) {
+        let attack = self.player.check_lock();
+        if attack > 0 {
+            let hole = ::rand::random::<u32>() % BOARD_WIDTH;
+            self.connection.send(&Json::object(vec![
+                ("type", "attack".into()),
+                ("lines", attack.into()),
+                ("hole", hole.into()),
+            ]));
+        }
+        if self.player.game.pieces() != self.sent_pieces {
+            self.sent_pieces = self.player.game.pieces();
+            self.send_state();
+        }
+        if self.player.game.game_over().is_some() && !self.sent_game_over {
+            self.sent_game_over = true;
+            self.send_state();
+            let reason = self.player.game.game_over().map(|reason| reason.description());
+            self.connection.send(&Json::object(vec![("type", "game_over".into()), ("reason", reason.into())]));
+        }
+    }
+
+    fn send_state(&mut self) {
+        let game = &self.player.game;
+        let queue: Vec<Json> = game.queue().iter().map(|piece| piece.name().into()).collect();
+        let state = Json::object(vec![
+            ("type", "state".into()),
+            ("board", board_to_json(game.board())),
+            ("score", game.score().into()),
+            ("lines", game.lines().into()),
+            ("level", game.level().into()),
+            ("hold", game.held_piece().map(|piece| piece.name()).into()),
+            ("queue", queue.into()),
+            ("incoming", self.player.incoming().into()),
+        ]);
+        self.connection.send(&state);
+    }
+
+    /// Handles the messages which have arrived from the other player. Returns true if any changed the battle.
+    fn receive(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(message) = self.connection.try_recv() {
+            let number = |key: &str| message.get(key).and_then(Json::as_i64).unwrap_or(0) as u32;
+            match message.get("type").and_then(Json::as_str).unwrap_or("") {
+                "state" => {
+                    let opponent = &mut self.opponent;
+                    read_board(message.get("board").unwrap_or(&Json::Null), &mut opponent.board);
+                    opponent.score = number("score");
+                    opponent.lines = number("lines");
+                    opponent.level = number("level");
+                    opponent.hold = message.get("hold").and_then(Json::as_str).map(str::to_string);
+                    // The queue is drawn beside the board, which only has room for the rules' previews.
+                    opponent.queue = message.get("queue").and_then(Json::as_array).unwrap_or(&[]).iter()
+                        .take(self.player.game.rules().previews)
+                        .filter_map(|name| name.as_str().map(str::to_string))
+                        .collect();
+                    opponent.incoming = number("incoming");
+                },
+                "attack" if !self.is_over() => {
+                    // No attack can send more rows than the board holds.
+                    let lines = cmp::min(number("lines"), BOARD_HEIGHT);
+                    self.player.receive(lines, number("hole") % BOARD_WIDTH);
+                    self.send_state();
+                },
+                "ping" => {
+                    let time = message.get("time").cloned().unwrap_or(Json::Null);
+                    self.connection.send(&Json::object(vec![("type", "pong".into()), ("time", time)]));
+                },
+                "pong" => {
+                    let sent = message.get("time").and_then(Json::as_f64).unwrap_or(0.0);
+                    self.latency = Some((self.millis() - sent).max(0.0) as u32);
+                },
+                "game_over" => {
+                    let reason = message.get("reason").and_then(Json::as_str).unwrap_or("Game over");
+                    self.opponent.game_over = Some(reason.to_string());
+                },
+                _ => (),
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Draws a piece from the opponent's preview, if it's one of the set being played.
+    fn render_remote_piece(&self, display: &mut Display, name: &str, origin: Point) {
+        if let Some(def) = self.pieces.pieces.iter().find(|def| def.name == name) {
+            Game::render_preview(display, &Piece::new(def), origin);
+        }
+    }
+}
+
+impl Battle for NetBattle {
+    /// Only the player at this keyboard plays here, and they can't undo or redo.
+    fn press(&mut self, _: usize, action: Action, repeat: bool) -> bool {
+        if self.is_over() || action == Action::Undo || action == Action::Redo {
+            return false;
+        }
+        let changed = self.player.game.press(action, repeat);
+        self.send_updates();
+        changed
+    }
+
+    fn set_held_actions(&mut self, _: usize, actions: Vec<Action>) {
+        self.player.game.set_held_actions(actions);
+    }
+
+    /// Runs a frame of the game, handling whatever the other player has sent and measuring the latency every
+    /// second.
+    fn update(&mut self) -> bool {
+        let was_closed = self.connection.closed;
+        let mut changed = self.receive();
+        if !self.is_over() {
+            changed = self.player.game.update() || changed;
+            self.send_updates();
+        }
+
+        self.frames += 1;
+        if self.frames.is_multiple_of(PING_INTERVAL) && !self.connection.closed {
+            let time = self.millis();
+            self.connection.send(&Json::object(vec![("type", "ping".into()), ("time", time.into())]));
+            changed = true;
+        }
+        changed || self.connection.closed != was_closed
+    }
+
+    fn render(&self, display: &mut Display) {
+        let remote_over = self.opponent.game_over.is_some();
+        let local_over = self.player.game.game_over().is_some();
+
+        self.player.game.render(display);
+        let status = if remote_over && !local_over { Some("Winner!") } else { None };
+        render_status(display, "You", self.player.incoming(), status);
+
+        display.set_origin(PLAYER_WIDTH);
+        let left_margin = BOARD_WIDTH * 2 + 5;
+        self.opponent.board.render(display, StackVisibility::Visible, 0);
+        display.set_text(self.player.game.rules().mode.name(), left_margin, 0, Color::Red, Color::Black);
+        display.set_text(format!("Score: {}", self.opponent.score), left_margin, 2, Color::Red, Color::Black);
+        display.set_text(format!("Lines: {}", self.opponent.lines), left_margin, 4, Color::Red, Color::Black);
+        if !self.opponent.queue.is_empty() {
+            display.set_text("Next pieces:", left_margin, 6, Color::Red, Color::Black);
+        }
+        for (i, name) in self.opponent.queue.iter().enumerate() {
+            let origin = Point{ x: left_margin as i32 + 2 + 10 * i as i32, y: 7 };
+            self.render_remote_piece(display, name, origin);
+        }
+        if let Some(ref name) = self.opponent.hold {
+            display.set_text("Hold:", left_margin, 13, Color::Red, Color::Black);
+            self.render_remote_piece(display, name, Point{ x: left_margin as i32 + 2, y: 14 });
+        }
+        if let Some(latency) = self.latency {
+            display.set_text(format!("Ping: {} ms", latency), left_margin, 18, Color::Red, Color::Black);
+        }
+        let status = if remote_over && !local_over {
+            Some("Game over!")
+        } else if local_over && !remote_over {
+            Some("Winner!")
+        } else if self.connection.closed && !remote_over {
+            Some("Disconnected")
+        } else {
+            None
+        };
+        render_status(display, "Opponent", self.opponent.incoming, status);
+        display.set_origin(0);
+    }
+
+    /// The opponent's game is described from the last state they sent, which doesn't include their current
+    /// piece.
+    fn spectator_states(&self) -> Vec<Json> {
+        let opponent = &self.opponent;
+        let rules = self.player.game.rules();
+        let queue: Vec<Json> = opponent.queue.iter().map(|name| name.as_str().into()).collect();
+        vec![
+            player_state("You", &self.player.game, self.player.incoming()),
+            Json::object(vec![
+                ("name", "Opponent".into()),
+                ("mode", rules.mode.name().into()),
+                ("pieces", rules.piece_set.name.as_str().into()),
+                ("board", board_to_json(&opponent.board)),
+                ("current", Json::Null),
+                ("queue", queue.into()),
+                ("hold", opponent.hold.as_deref().into()),
+                ("score", opponent.score.into()),
+                ("level", opponent.level.into()),
+                ("lines", opponent.lines.into()),
+                ("game_over", opponent.game_over.as_deref().into()),
+                ("incoming", opponent.incoming.into()),
+            ]),
+        ]
+    }
+}
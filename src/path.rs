@@ -0,0 +1,180 @@
+use ai::Placement;
+use board::Board;
+use game::Action;
+use piece::Piece;
+use std::collections::{HashSet, VecDeque};
+use util::*;
+
+/// The ways the player can move a piece under the game's rules, as the pathfinder needs to know them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Movement {
+    pub rotate_180: bool,
+    pub hard_drop: bool,
+    /// Set at 20G, where the piece falls onto the stack after every action.
+    pub instant_gravity: bool,
+}
+
+/// A position the piece can be moved to, along with the node it was reached from and the action that took it
+/// there.
+struct Node {
+    piece: Piece,
+    position: Point,
+    parent: Option<(usize, Action)>,
+}
+
+/// Searches every position the piece at `origin` can be moved to, breadth first so that each is reached with
+/// as few actions as possible. The first node is the starting position.
+///
+/// Soft drops count one action for each row, unless `held` is set, in which case holding a move or soft drop
+/// until the piece can go no further also counts as one action, as players are judged on their finesse.
+fn explore(board: &Board, piece: &Piece, origin: Point, movement: Movement, held: bool) -> Vec<Node> {
+    // Each action, and whether its key is held down.
+    let mut actions = vec![(Action::MoveLeft, false), (Action::MoveRight, false), (Action::RotateRight, false),
+                           (Action::RotateLeft, false)];
+    if movement.rotate_180 {
+        actions.push((Action::Rotate180, false));
+    }
+    actions.push((Action::SoftDrop, false));
+    if held {
+        actions.extend(vec![(Action::MoveLeft, true), (Action::MoveRight, true), (Action::SoftDrop, true)]);
+    }
+
+    let mut nodes = vec![Node{ piece: piece.clone(), position: origin, parent: None }];
+    let mut seen = HashSet::new();
+    seen.insert((piece.rotation(), origin.x, origin.y));
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        for &(action, held) in &actions {
+            let next = {
+                let node = &nodes[index];
+                let scale = node.piece.scale();
+                let shift = |x, y| {
+                    Some((node.piece.clone(), Point{ x: node.position.x + x, y: node.position.y + y }))
+                };
+                let moved = match action {
+                    Action::MoveLeft => shift(-scale, 0),
+                    Action::MoveRight => shift(scale, 0),
+                    Action::SoftDrop => shift(0, scale),
+                    Action::RotateLeft => rotate(board, node, Direction::Left),
+                    Action::RotateRight => rotate(board, node, Direction::Right),
+                    Action::Rotate180 => rotate(board, node, Direction::Half),
+                    _ => None,
+                };
+                let moved = match moved {
+                    Some((piece, position)) if held => {
+                        let (dx, dy) = (position.x - node.position.x, position.y - node.position.y);
+                        let mut last = node.position;
+                        let mut next = position;
+                        while !board.collision_test(&piece, next) {
+                            last = next;
+                            next = Point{ x: next.x + dx, y: next.y + dy };
+                        }
+                        Some((piece, last))
+                    },
+                    moved => moved,
+                };
+                match moved {
+                    Some((ref piece, position)) if board.collision_test(piece, position) => None,
+                    Some((piece, position)) if movement.instant_gravity => {
+                        let position = dropped_position(board, &piece, position);
+                        Some((piece, position))
+                    },
+                    moved => moved,
+                }
+            };
+
+            if let Some((piece, position)) = next {
+                if seen.insert((piece.rotation(), position.x, position.y)) {
+                    queue.push_back(nodes.len());
+                    nodes.push(Node{ piece, position, parent: Some((index, action)) });
+                }
+            }
+        }
+    }
+    nodes
+}
+
+fn rotate(board: &Board, node: &Node, direction: Direction) -> Option<(Piece, Point)> {
+    board.rotate_piece(&node.piece, node.position, direction).map(|(piece, position, _)| (piece, position))
+}
+
+/// Returns the position the piece comes to rest at if it is dropped from `origin`.
+fn dropped_position(board: &Board, piece: &Piece, origin: Point) -> Point {
+    let mut position = origin;
+    while !board.collision_test(piece, Point{ y: position.y + piece.scale(), ..position }) {
+        position.y += piece.scale();
+    }
+    position
+}
+
+/// Returns the actions which take the search back from the starting position to the node at `index`.
+fn actions_to(nodes: &[Node], mut index: usize) -> Vec<Action> {
+    let mut actions = Vec::new();
+    while let Some((parent, action)) = nodes[index].parent {
+        actions.push(action);
+        index = parent;
+    }
+    actions.reverse();
+    actions
+}
+
+/// Finds every placement the piece at `origin` can reach by any combination of moves, rotations and soft
+/// drops, including tucks under overhangs and spins into gaps that a straight drop can't reach. Placements
+/// which cover the same cells are only returned once.
+pub fn reachable_placements(board: &Board, piece: &Piece, origin: Point, movement: Movement) -> Vec<Placement> {
+    let mut found: Vec<Placement> = Vec::new();
+    for node in explore(board, piece, origin, movement, false) {
+        if board.collision_test(&node.piece, Point{ y: node.position.y + node.piece.scale(), ..node.position }) {
+            let placement = Placement{ piece: node.piece, position: node.position, hold: false };
+            let cells = placement.cells();
+            if !found.iter().any(|p| p.cells() == cells) {
+                found.push(placement);
+            }
+        }
+    }
+    found
+}
+
+/// Finds the shortest sequence of actions which takes the piece at `origin` to the cells of `target` and locks
+/// it there: a hard drop once the piece is above its target, or, without hard drops, a soft drop once it's
+/// resting in place. Returns `None` if the target can't be reached.
+pub fn find_path(board: &Board, piece: &Piece, origin: Point, target: &Placement, movement: Movement)
+                 -> Option<Vec<Action>> {
+    search(board, piece, origin, target, movement, false)
+}
+
+/// Returns the fewest key presses which can take the piece at `origin` to `target` and lock it there, where a
+/// key held until the piece can go no further counts as a single press. A player who uses more has made a
+/// finesse fault.
+pub fn finesse_presses(board: &Board, piece: &Piece, origin: Point, target: &Placement, movement: Movement)
+                       -> Option<usize> {
+    search(board, piece, origin, target, movement, true).map(|actions| actions.len())
+}
+
+fn search(board: &Board, piece: &Piece, origin: Point, target: &Placement, movement: Movement, held: bool)
+          -> Option<Vec<Action>> {
+    let cells = target.cells();
+    let nodes = explore(board, piece, origin, movement, held);
+    let covers = |piece: &Piece, position: Point| {
+        Placement{ piece: piece.clone(), position, hold: false }.cells() == cells
+    };
+
+    // Nodes are in order of the number of actions taken to reach them, so the first found is the best.
+    for (index, node) in nodes.iter().enumerate() {
+        if movement.hard_drop {
+            if covers(&node.piece, dropped_position(board, &node.piece, node.position)) {
+                let mut actions = actions_to(&nodes, index);
+                actions.push(Action::HardDrop);
+                return Some(actions);
+            }
+        } else if covers(&node.piece, node.position) &&
+            board.collision_test(&node.piece, Point{ y: node.position.y + node.piece.scale(), ..node.position }) {
+            let mut actions = actions_to(&nodes, index);
+            actions.push(Action::SoftDrop);
+            return Some(actions);
+        }
+    }
+    None
+}
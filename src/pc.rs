@@ -0,0 +1,360 @@
+use ai::Placement;
+use board::*;
+use game::Game;
+use path::{reachable_placements, Movement};
+use piece::Piece;
+use std::cmp;
+use std::collections::HashSet;
+use util::*;
+
+/// The most rows a perfect clear is searched for in.
+pub const MAX_HEIGHT: usize = 4;
+
+/// The pieces the solver can place: the current piece where it is now, the piece in hold and the pieces
+/// previewed after them.
+pub struct Pieces<'a> {
+    pub current: &'a Piece,
+    pub origin: Point,
+    pub hold: Option<&'a Piece>,
+    pub queue: &'a [Piece],
+    /// Whether the rules allow hold, and whether it has already been used on the current piece.
+    pub can_hold: bool,
+    pub hold_used: bool,
+}
+
+/// Searches for a sequence of placements which clears every block from the board, using only the pieces given
+/// and no more than `MAX_HEIGHT` rows. The placements are in the order they're made, each on the board left by
+/// the ones before it, and those which swap the piece with hold have `hold` set. Every way of placing the
+/// pieces is tried, so `None` means that no perfect clear can be made with them. Pieces of any size can be
+/// placed, including sets which mix sizes.
+pub fn solve(board: &Board, pieces: &Pieces, movement: Movement) -> Option<Vec<Placement>> {
+    let filled = filled_cells(board, BOARD_HEIGHT as usize);
+    let top = board.cells.iter().position(|row| row.iter().any(|cell| cell.is_some()));
+    let stack_height = top.map_or(0, |top| BOARD_HEIGHT as usize - top);
+    if stack_height > MAX_HEIGHT {
+        return None;
+    }
+
+    let mut sequence = vec![pieces.current.clone()];
+    sequence.extend(pieces.queue.iter().cloned());
+    // Any area the pieces fill must be a multiple of the size they all share a factor of.
+    let unit = sequence.iter().chain(pieces.hold).map(Piece::blocks).fold(0, gcd);
+    let mut search = Search{
+        sequence,
+        unit,
+        origin: pieces.origin,
+        movement,
+        can_hold: pieces.can_hold,
+        hold_used: pieces.hold_used,
+        failed: HashSet::new(),
+        placements: Vec::new(),
+    };
+
+    // Try the lowest heights first, as they take the fewest pieces. Only heights whose empty cells can be
+    // filled by whole pieces are worth trying. An empty board needs at least a row to be cleared.
+    for height in cmp::max(stack_height, 1)..(MAX_HEIGHT + 1) {
+        let node = Node{ board: board.clone(), height, index: 0, hold: pieces.hold.cloned() };
+        if (height * BOARD_WIDTH as usize - filled).is_multiple_of(unit) && fillable(&node.board, height, unit) &&
+            search.search(&node) {
+            return Some(search.placements);
+        }
+    }
+    None
+}
+
+/// A state of the search: the board, the number of rows at its bottom which must still be cleared, the index
+/// into the sequence of the next piece to be dealt and the piece in hold.
+struct Node {
+    board: Board,
+    height: usize,
+    index: usize,
+    hold: Option<Piece>,
+}
+
+struct Search {
+    /// The current piece followed by the queue.
+    sequence: Vec<Piece>,
+    /// The greatest common divisor of the pieces' sizes.
+    unit: usize,
+    origin: Point,
+    movement: Movement,
+    can_hold: bool,
+    hold_used: bool,
+    /// States already found to lead nowhere, by their board, height, next piece and held piece.
+    failed: HashSet<(u64, usize, usize, Option<String>)>,
+    /// The placements made on the way to the state being searched.
+    placements: Vec<Placement>,
+}
+
+impl Search {
+    /// Returns true, leaving the placements which make it in `placements`, if a perfect clear can be made
+    /// from this state.
+    fn search(&mut self, node: &Node) -> bool {
+        if node.height == 0 {
+            return true;
+        }
+        let empty = node.height * BOARD_WIDTH as usize - filled_cells(&node.board, node.height);
+        let remaining = self.sequence.iter().skip(node.index).chain(node.hold.as_ref());
+        let available: usize = remaining.map(Piece::blocks).sum();
+        if node.index >= self.sequence.len() || empty > available {
+            return false;
+        }
+        let key = (bits(&node.board), node.height, node.index, node.hold.as_ref().map(|p| p.name().to_string()));
+        if self.failed.contains(&key) {
+            return false;
+        }
+
+        let piece = self.sequence[node.index].clone();
+        let origin = if node.index == 0 { self.origin } else { entry_position(&piece, node.height) };
+        if self.place(node, &piece, origin, false) {
+            return true;
+        }
+
+        // Swapping in a piece of the same kind couldn't make any placement that playing it straight can't.
+        if self.can_hold && !(node.index == 0 && self.hold_used) {
+            let swapped = match node.hold {
+                Some(ref held) => Some(held.clone()),
+                None => self.sequence.get(node.index + 1).cloned(),
+            };
+            if let Some(swapped) = swapped {
+                if swapped.name() != piece.name() {
+                    let origin = entry_position(&swapped, node.height);
+                    if self.place(node, &swapped, origin, true) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        self.failed.insert(key);
+        false
+    }
+
+    /// Tries every placement of `piece` within the rows still to be cleared, searching on from each. `hold` is
+    /// set if the piece was swapped in from hold, or from the queue if nothing was held.
+    fn place(&mut self, node: &Node, piece: &Piece, origin: Point, hold: bool) -> bool {
+        let (index, held) = if hold {
+            let mut held = self.sequence[node.index].clone();
+            held.reset_rotation();
+            (node.index + if node.hold.is_some() { 1 } else { 2 }, Some(held))
+        } else {
+            (node.index + 1, node.hold.clone())
+        };
+
+        let top = (BOARD_HEIGHT as usize - node.height) as i32;
+        for placement in reachable_placements(&node.board, piece, origin, self.movement) {
+            if placement.cells().iter().any(|cell| cell.y < top) {
+                continue;
+            }
+            let (board, lines) = placement.result(&node.board);
+            let height = node.height - lines as usize;
+            if !fillable(&board, height, self.unit) {
+                continue;
+            }
+
+            self.placements.push(Placement{ hold, ..placement });
+            let next = Node{ board, height, index, hold: held.clone() };
+            if self.search(&next) {
+                return true;
+            }
+            self.placements.pop();
+        }
+        false
+    }
+}
+
+/// Returns where a piece from the queue or hold starts its search: just above the rows being cleared. Nothing
+/// is locked above them, so the piece can reach the same placements from here as from where it spawns.
+fn entry_position(piece: &Piece, height: usize) -> Point {
+    Point{
+        x: Game::spawn_position(piece).x,
+        y: (BOARD_HEIGHT as usize - height - piece.size()) as i32,
+    }
+}
+
+/// Returns the number of filled cells in the bottom `height` rows.
+fn filled_cells(board: &Board, height: usize) -> usize {
+    board.cells.iter().rev().take(height).flat_map(|row| row.iter()).filter(|cell| cell.is_some()).count()
+}
+
+/// Packs the filled cells of the bottom `MAX_HEIGHT` rows into bits.
+fn bits(board: &Board) -> u64 {
+    let mut bits = 0;
+    for row in board.cells.iter().rev().take(MAX_HEIGHT) {
+        for cell in row.iter() {
+            bits = bits << 1 | if cell.is_some() { 1 } else { 0 };
+        }
+    }
+    bits
+}
+
+/// Returns false if the empty cells in the bottom `height` rows can't be filled by whole pieces: that is, if
+/// they make up an area whose size isn't a multiple of `unit`.
+fn fillable(board: &Board, height: usize, unit: usize) -> bool {
+    let top = BOARD_HEIGHT as usize - height;
+    let mut seen = [[false; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+    for row in top..BOARD_HEIGHT as usize {
+        for col in 0..BOARD_WIDTH as usize {
+            if seen[row][col] || board.cells[row][col].is_some() {
+                continue;
+            }
+            // Flood fill the area from this cell.
+            let mut size = 0;
+            let mut stack = vec![(row, col)];
+            seen[row][col] = true;
+            while let Some((row, col)) = stack.pop() {
+                size += 1;
+                let neighbours = [(row.wrapping_sub(1), col), (row + 1, col), (row, col.wrapping_sub(1)),
+                                  (row, col + 1)];
+                for &(r, c) in &neighbours {
+                    if r >= top && r < BOARD_HEIGHT as usize && c < BOARD_WIDTH as usize && !seen[r][c] &&
+                        board.cells[r][c].is_none() {
+                        seen[r][c] = true;
+                        stack.push((r, c));
+                    }
+                }
+            }
+            if size % unit != 0 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piece::PieceSet;
+
+    fn piece(set: &str, name: &str) -> Piece {
+        let set = PieceSet::builtin(set).unwrap();
+        Piece::new(set.pieces.iter().find(|def| def.name == name).unwrap())
+    }
+
+    fn movement() -> Movement {
+        Movement{ rotate_180: true, hard_drop: true, instant_gravity: false }
+    }
+
+    /// Returns a board whose bottom rows are filled except for the given columns of each, listed from the
+    /// bottom row up.
+    fn board_with_gaps(gaps: &[&[usize]]) -> Board {
+        let mut board = Board::new();
+        for (i, columns) in gaps.iter().enumerate() {
+            let row = BOARD_HEIGHT as usize - 1 - i;
+            for col in 0..BOARD_WIDTH as usize {
+                if !columns.contains(&col) {
+                    board.cells[row][col] = Some(Color::Gray);
+                }
+            }
+        }
+        board
+    }
+
+    fn solve_with(board: &Board, current: &str, hold: Option<&str>, queue: &[&str]) -> Option<Vec<Placement>> {
+        solve_in("tetromino", board, current, hold, queue)
+    }
+
+    fn solve_in(set: &str, board: &Board, current: &str, hold: Option<&str>, queue: &[&str])
+                -> Option<Vec<Placement>> {
+        let current = piece(set, current);
+        let hold = hold.map(|name| piece(set, name));
+        let queue: Vec<Piece> = queue.iter().map(|&name| piece(set, name)).collect();
+        let pieces = Pieces{
+            current: &current,
+            origin: Game::spawn_position(&current),
+            hold: hold.as_ref(),
+            queue: &queue,
+            can_hold: true,
+            hold_used: false,
+        };
+        solve(board, &pieces, movement())
+    }
+
+    /// Makes the placements one after another, returning true if they leave the board empty.
+    fn clears_board(board: &Board, placements: &[Placement]) -> bool {
+        let mut board = board.clone();
+        for placement in placements {
+            board = placement.result(&board).0;
+        }
+        board.cells.iter().all(|row| row.iter().all(|cell| cell.is_none()))
+    }
+
+    #[test]
+    fn finds_a_one_row_clear() {
+        let board = board_with_gaps(&[&[0, 1, 2, 3]]);
+        let placements = solve_with(&board, "I", None, &["O", "T"]).unwrap();
+        assert_eq!(placements.len(), 1);
+        assert_eq!(placements[0].piece.name(), "I");
+        assert!(!placements[0].hold);
+        assert!(clears_board(&board, &placements));
+    }
+
+    #[test]
+    fn finds_a_clear_using_hold() {
+        let board = board_with_gaps(&[&[6, 7, 8, 9]]);
+        let placements = solve_with(&board, "O", Some("I"), &["O"]).unwrap();
+        assert_eq!(placements.len(), 1);
+        assert_eq!(placements[0].piece.name(), "I");
+        assert!(placements[0].hold);
+        assert!(clears_board(&board, &placements));
+    }
+
+    #[test]
+    fn proves_a_clear_impossible() {
+        // Four cells in a row can't be filled by O pieces, and there aren't enough pieces for a taller clear.
+        let board = board_with_gaps(&[&[0, 1, 2, 3]]);
+        assert!(solve_with(&board, "O", None, &["O", "O"]).is_none());
+        assert!(solve_with(&board, "O", Some("O"), &["O", "O"]).is_none());
+    }
+
+    #[test]
+    fn finds_an_opening_clear() {
+        let board = Board::new();
+        let placements = solve_with(&board, "I", None, &["I", "I", "I", "O", "T"]).unwrap();
+        assert_eq!(placements.len(), 5);
+        assert!(clears_board(&board, &placements));
+    }
+
+    #[test]
+    fn finds_clears_with_other_piece_sizes() {
+        let board = board_with_gaps(&[&[0, 1, 2, 3, 4]]);
+        let placements = solve_in("pentomino", &board, "I", None, &["T"]).unwrap();
+        assert_eq!(placements.len(), 1);
+        assert!(clears_board(&board, &placements));
+
+        // Three trominoes can't fill four cells, but a tromino and the monomino can.
+        let board = board_with_gaps(&[&[0, 1, 2, 3]]);
+        assert!(solve_in("tromino", &board, "I", None, &["I", "L"]).is_none());
+        let placements = solve_in("tromino", &board, "I", None, &["O"]).unwrap();
+        assert_eq!(placements.len(), 2);
+        assert!(clears_board(&board, &placements));
+    }
+
+    #[test]
+    fn checks_areas_can_be_filled() {
+        assert!(fillable(&board_with_gaps(&[&[0, 1, 2, 3]]), 1, 4));
+        assert!(fillable(&board_with_gaps(&[&[0, 1], &[0, 1]]), 2, 4));
+        // Areas of one and three cells, and of two and two.
+        assert!(!fillable(&board_with_gaps(&[&[0, 5, 6, 7]]), 1, 4));
+        assert!(!fillable(&board_with_gaps(&[&[0, 1, 8, 9]]), 1, 4));
+        // Areas of five cells, for pentominoes.
+        assert!(fillable(&board_with_gaps(&[&[0, 1, 2, 5, 6], &[0, 1, 5, 6, 7]]), 2, 5));
+        assert!(!fillable(&board_with_gaps(&[&[0, 1, 2, 3]]), 1, 5));
+    }
+
+    #[test]
+    fn keys_boards_by_their_bottom_rows() {
+        let a = board_with_gaps(&[&[0, 1, 2, 3]]);
+        let b = board_with_gaps(&[&[1, 2, 3, 4]]);
+        let c = board_with_gaps(&[&[], &[], &[], &[], &[0]]);
+        assert_eq!(bits(&a), bits(&a.clone()));
+        assert!(bits(&a) != bits(&b));
+        // Only the rows a perfect clear is searched in are part of the key.
+        assert_eq!(bits(&c), bits(&board_with_gaps(&[&[], &[], &[], &[]])));
+    }
+}
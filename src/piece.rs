@@ -0,0 +1,612 @@
+use board::{BOARD_WIDTH, HIDDEN_ROWS};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+use util::*;
+
+/// The sets of pieces which are compiled into the game, by name.
+const BUILTIN_SETS: [(&str, &str); 5] = [
+    ("tetromino", include_str!("../pieces/tetromino.pieces")),
+    ("tetromino-ars", include_str!("../pieces/tetromino-ars.pieces")),
+    ("tetromino-nes", include_str!("../pieces/tetromino-nes.pieces")),
+    ("pentomino", include_str!("../pieces/pentomino.pieces")),
+    ("tromino", include_str!("../pieces/tromino.pieces")),
+];
+
+/// A list of offsets to try, in order, when rotating a piece from one rotation state to another. The first
+/// offset at which the rotated piece fits is used.
+#[derive(Debug, Default)]
+pub struct KickTable {
+    transitions: HashMap<(usize, usize), Vec<Point>>,
+    fallback: Vec<Point>,
+    /// Enables the Arika rule for L, J and T: a piece whose rotation is first blocked by a block in the middle
+    /// column of its box may not kick.
+    center_column: bool,
+}
+
+impl KickTable {
+    /// Returns the offsets to test when rotating from the state `from` to the state `to`. A rotation which the
+    /// table says nothing about is only tried in place.
+    pub fn offsets(&self, from: usize, to: usize) -> &[Point] {
+        const IN_PLACE: &[Point] = &[Point{ x: 0, y: 0 }];
+
+        match self.transitions.get(&(from, to)) {
+            Some(offsets) => offsets,
+            None if !self.fallback.is_empty() => &self.fallback,
+            None => IN_PLACE,
+        }
+    }
+}
+
+/// The rotation systems available for the standard tetrominoes. Each is described by its own built-in piece
+/// set.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RotationSystem {
+    /// The Super Rotation System used by modern guideline games.
+    Srs,
+    /// The Arika Rotation System used by the Tetris The Grand Master arcade games.
+    Ars,
+}
+
+impl RotationSystem {
+    pub fn from_name(name: &str) -> Option<RotationSystem> {
+        match name {
+            "srs" => Some(RotationSystem::Srs),
+            "ars" => Some(RotationSystem::Ars),
+            _ => None,
+        }
+    }
+
+    pub fn piece_set(&self) -> PieceSet {
+        match *self {
+            RotationSystem::Srs => PieceSet::builtin("tetromino").unwrap(),
+            RotationSystem::Ars => PieceSet::builtin("tetromino-ars").unwrap(),
+        }
+    }
+}
+
+/// Describes a single kind of piece: its color, the shape of each of its four rotation states, the state it
+/// spawns in and the kicks used to rotate it.
+#[derive(Debug)]
+pub struct PieceDef {
+    pub name: String,
+    pub color: Color,
+    pub spawn_rotation: usize,
+    states: Vec<Vec<Vec<u8>>>,
+    kicks: Arc<KickTable>,
+}
+
+/// A piece of a particular kind in one of its rotation states.
+#[derive(Clone)]
+pub struct Piece {
+    def: Arc<PieceDef>,
+    rotation: usize,
+    /// The number of board cells across each of the piece's blocks. Big mode plays with pieces at scale 2.
+    scale: i32,
+}
+
+impl Piece {
+    pub fn new(def: &Arc<PieceDef>) -> Piece {
+        Piece{
+            def: def.clone(),
+            rotation: def.spawn_rotation,
+            scale: 1,
+        }
+    }
+
+    /// Returns a copy of this piece drawn at a different scale.
+    pub fn with_scale(&self, scale: i32) -> Piece {
+        Piece{
+            def: self.def.clone(),
+            rotation: self.rotation,
+            scale,
+        }
+    }
+
+    pub fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    /// Returns the rotation state the piece is in, from 0 to 3 clockwise.
+    pub fn rotation(&self) -> usize {
+        self.rotation
+    }
+
+    pub fn color(&self) -> Color {
+        self.def.color
+    }
+
+    pub fn name(&self) -> &str {
+        &self.def.name
+    }
+
+    /// Returns true if the cell at `row` and `col` of the piece's box is one of its blocks.
+    pub fn is_block(&self, row: i32, col: i32) -> bool {
+        let shape = &self.def.states[self.rotation];
+        if row < 0 || col < 0 {
+            return false;
+        }
+        let (row, col) = ((row / self.scale) as usize, (col / self.scale) as usize);
+        row < shape.len() && col < shape.len() && shape[row][col] != 0
+    }
+
+    /// The width and height of the square box the piece rotates within, in board cells.
+    pub fn size(&self) -> usize {
+        self.def.states[0].len() * self.scale as usize
+    }
+
+    /// The number of blocks the piece is made of.
+    pub fn blocks(&self) -> usize {
+        self.def.states[0].iter().map(|row| row.iter().filter(|&&cell| cell != 0).count()).sum()
+    }
+
+    /// Returns the piece to the rotation state it spawns in.
+    pub fn reset_rotation(&mut self) {
+        self.rotation = self.def.spawn_rotation;
+    }
+
+    pub fn rotate(&mut self, direction: Direction) {
+        self.rotation = rotate_state(self.rotation, direction);
+    }
+
+    /// Returns the offsets to try, in board coordinates, when rotating this piece in the specified direction.
+    pub fn kicks(&self, direction: Direction) -> Vec<Point> {
+        self.def.kicks.offsets(self.rotation, rotate_state(self.rotation, direction)).iter()
+            .map(|offset| Point{ x: offset.x * self.scale, y: offset.y * self.scale })
+            .collect()
+    }
+
+    /// Returns true if this piece may only kick when its rotation wasn't blocked in the middle column of its
+    /// box, as in the Arika Rotation System.
+    pub fn center_column_rule(&self) -> bool {
+        self.def.kicks.center_column
+    }
+
+    /// Calls `callback` with the row and column, within the piece's box, of each board cell the piece covers.
+    pub fn each_point(&self, callback: &mut dyn FnMut(i32, i32)) {
+        let shape = &self.def.states[self.rotation];
+        let piece_width = shape.len() as i32;
+        for row in 0..piece_width {
+            for col in 0..piece_width {
+                if shape[row as usize][col as usize] != 0 {
+                    for dy in 0..self.scale {
+                        for dx in 0..self.scale {
+                            callback(row * self.scale + dy, col * self.scale + dx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn rotate_state(rotation: usize, direction: Direction) -> usize {
+    match direction {
+        Direction::Left => (rotation + 3) % 4,
+        Direction::Right => (rotation + 1) % 4,
+        Direction::Half => (rotation + 2) % 4,
+    }
+}
+
+/// Rotates a square grid a quarter turn clockwise.
+fn rotate_shape(shape: &mut [Vec<u8>]) {
+    let size = shape.len();
+
+    for row in 0..size/2 {
+        for col in row..(size - row - 1) {
+            let t = shape[row][col];
+            shape[row][col] = shape[size - col - 1][row];
+            shape[size - col - 1][row] = shape[size - row - 1][size - col - 1];
+            shape[size - row - 1][size - col - 1] = shape[col][size - row - 1];
+            shape[col][size - row - 1] = t;
+        }
+    }
+}
+
+/// A named collection of piece kinds which the game draws from.
+///
+/// Piece sets are described in a simple line-based text format. Blank lines and anything following a `#`
+/// are ignored. A set is made up of `kicks` and `piece` blocks, each terminated by `end`:
+///
+/// ```text
+/// kicks jlstz
+/// 0>1 (0,0) (-1,0) (-1,1) (0,-2) (-1,-2)
+/// * (0,0)
+/// end
+///
+/// piece T
+/// color purple
+/// kicks jlstz
+/// spawn 0
+/// shape
+/// .X.
+/// XXX
+/// ...
+/// end
+/// ```
+///
+/// A kick table lists the offsets to try for each `from>to` rotation, where states are numbered clockwise
+/// from 0. Offsets use the convention of the guideline tables, so a positive y moves the piece up. A `*` row
+/// applies to any rotation which isn't listed, and a `center-column` line enables the Arika rule which stops
+/// a piece from kicking when its rotation is blocked in the middle column of its box.
+///
+/// A piece names a kick table defined earlier in the file, or `none` to only allow rotation in place. Its
+/// `shape` gives rotation state 0 as a square grid, where `.` is an empty cell and any other character is a
+/// block; the remaining states are found by rotating the grid. Any state can instead be given explicitly with
+/// a `state <n>` grid, which is how rotation systems that don't use true rotation are described. `spawn` is
+/// the state the piece enters the board in.
+#[derive(Clone)]
+pub struct PieceSet {
+    /// The name of a built-in set, or the path of the file the set was loaded from.
+    pub name: String,
+    pub pieces: Vec<Arc<PieceDef>>,
+}
+
+impl PieceSet {
+    /// Returns one of the built-in piece sets.
+    pub fn builtin(name: &str) -> Option<PieceSet> {
+        BUILTIN_SETS.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(n, text)| PieceSet::parse(n, text).unwrap())
+    }
+
+    pub fn builtin_names() -> Vec<&'static str> {
+        BUILTIN_SETS.iter().map(|&(n, _)| n).collect()
+    }
+
+    /// Returns the built-in piece set with the given name, or otherwise loads a piece set from the file at
+    /// that path.
+    pub fn find(name: &str) -> Result<PieceSet, String> {
+        use std::io::Read;
+
+        if let Some(set) = PieceSet::builtin(name) {
+            return Ok(set);
+        }
+
+        let mut text = String::new();
+        match File::open(name).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => PieceSet::parse(name, &text),
+            Err(err) => Err(format!("could not read piece set {}: {}", name, err)),
+        }
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<PieceSet, String> {
+        let mut parser = Parser{
+            lines: text.lines().enumerate(),
+            kick_tables: HashMap::new(),
+        };
+        let mut set = PieceSet{
+            name: name.to_string(),
+            pieces: Vec::new(),
+        };
+
+        while let Some((n, line)) = parser.next_line() {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("kicks"), Some(table), None) => {
+                    let kicks = parser.parse_kicks()?;
+                    parser.kick_tables.insert(table.to_string(), Arc::new(kicks));
+                },
+                (Some("piece"), Some(piece), None) => {
+                    let def = parser.parse_piece(piece)?;
+                    if set.pieces.iter().any(|p| p.name == def.name) {
+                        return Err(format!("line {}: duplicate piece {}", n, def.name));
+                    }
+                    set.pieces.push(Arc::new(def));
+                },
+                _ => return Err(format!("line {}: expected `kicks <name>` or `piece <name>`", n)),
+            }
+        }
+
+        if set.pieces.is_empty() {
+            return Err(format!("piece set {} contains no pieces", name));
+        }
+        Ok(set)
+    }
+}
+
+struct Parser<'a> {
+    lines: ::std::iter::Enumerate<::std::str::Lines<'a>>,
+    kick_tables: HashMap<String, Arc<KickTable>>,
+}
+
+impl<'a> Parser<'a> {
+    /// Returns the next non-blank line with comments removed, along with its 1-based line number.
+    fn next_line(&mut self) -> Option<(usize, &'a str)> {
+        for (i, line) in self.lines.by_ref() {
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => line,
+            }.trim();
+            if !line.is_empty() {
+                return Some((i + 1, line));
+            }
+        }
+        None
+    }
+
+    fn parse_kicks(&mut self) -> Result<KickTable, String> {
+        let mut table = KickTable::default();
+
+        loop {
+            let (n, line) = match self.next_line() {
+                Some(l) => l,
+                None => return Err("unexpected end of file in kicks block".to_string()),
+            };
+            if line == "end" {
+                return Ok(table);
+            }
+            if line == "center-column" {
+                table.center_column = true;
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let transition = words.next().unwrap();
+            let mut offsets = Vec::new();
+            for word in words {
+                match parse_offset(word) {
+                    Some(p) => offsets.push(p),
+                    None => return Err(format!("line {}: invalid offset `{}`", n, word)),
+                }
+            }
+
+            if transition == "*" {
+                table.fallback = offsets;
+                continue;
+            }
+            let states: Vec<Option<usize>> = transition.split('>').map(|s| s.parse().ok()).collect();
+            match states[..] {
+                [Some(from), Some(to)] if from < 4 && to < 4 => {
+                    table.transitions.insert((from, to), offsets);
+                },
+                _ => return Err(format!("line {}: invalid rotation `{}`", n, transition)),
+            }
+        }
+    }
+
+    fn parse_piece(&mut self, name: &str) -> Result<PieceDef, String> {
+        let mut color = None;
+        let mut kicks = None;
+        let mut spawn_rotation = 0;
+        let mut states: Vec<Option<Vec<Vec<u8>>>> = vec![None, None, None, None];
+
+        loop {
+            let (n, line) = match self.next_line() {
+                Some(l) => l,
+                None => return Err(format!("unexpected end of file in piece {}", name)),
+            };
+            if line == "end" {
+                break;
+            }
+
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("color"), Some(c), None) => {
+                    color = Color::from_name(c);
+                    if color.is_none() {
+                        return Err(format!("line {}: unknown color `{}`", n, c));
+                    }
+                },
+                (Some("kicks"), Some("none"), None) => kicks = Some(Arc::new(KickTable::default())),
+                (Some("kicks"), Some(k), None) => {
+                    match self.kick_tables.get(k) {
+                        Some(table) => kicks = Some(table.clone()),
+                        None => return Err(format!("line {}: unknown kick table `{}`", n, k)),
+                    }
+                },
+                (Some("spawn"), Some(s), None) => {
+                    match s.parse() {
+                        Ok(r) if r < 4 => spawn_rotation = r,
+                        _ => return Err(format!("line {}: invalid spawn rotation `{}`", n, s)),
+                    }
+                },
+                (Some("shape"), None, None) => {
+                    let mut shape = self.parse_grid(name)?;
+                    for state in states.iter_mut() {
+                        if state.is_none() {
+                            *state = Some(shape.clone());
+                        }
+                        rotate_shape(&mut shape);
+                    }
+                },
+                (Some("state"), Some(s), None) => {
+                    match s.parse::<usize>() {
+                        Ok(r) if r < 4 => states[r] = Some(self.parse_grid(name)?),
+                        _ => return Err(format!("line {}: invalid rotation state `{}`", n, s)),
+                    }
+                },
+                _ => return Err(format!("line {}: unexpected `{}` in piece {}", n, line, name)),
+            }
+        }
+
+        if states.iter().any(|s| s.is_none()) {
+            return Err(format!("piece {} needs a shape or all four rotation states", name));
+        }
+        let states: Vec<Vec<Vec<u8>>> = states.into_iter().map(|s| s.unwrap()).collect();
+        if states.iter().any(|s| s.len() != states[0].len()) {
+            return Err(format!("all rotation states of piece {} must be the same size", name));
+        }
+
+        Ok(PieceDef{
+            name: name.to_string(),
+            color: match color {
+                Some(c) => c,
+                None => return Err(format!("piece {} has no color", name)),
+            },
+            spawn_rotation,
+            states,
+            // Without a kick table, pieces may only rotate in place.
+            kicks: kicks.unwrap_or_else(|| Arc::new(KickTable::default())),
+        })
+    }
+
+    /// Reads a square grid of cells, where `.` is empty and anything else is a block. The width of the first
+    /// row determines how many rows are read.
+    fn parse_grid(&mut self, name: &str) -> Result<Vec<Vec<u8>>, String> {
+        let max_size = ::std::cmp::min(BOARD_WIDTH, HIDDEN_ROWS) as usize;
+        let mut grid: Vec<Vec<u8>> = Vec::new();
+        loop {
+            let line = match self.next_line() {
+                Some((_, l)) => l,
+                None => return Err(format!("unexpected end of file in piece {}", name)),
+            };
+            grid.push(line.chars().map(|c| if c == '.' { 0 } else { 1 }).collect());
+            // A piece has to fit across the board, and within the hidden rows it spawns in.
+            if grid[0].len() > max_size {
+                return Err(format!("piece {} is more than {} blocks across", name, max_size));
+            }
+            if grid.len() == grid[0].len() {
+                break;
+            }
+        }
+
+        if grid.iter().any(|row| row.len() != grid.len()) {
+            return Err(format!("piece {} must have a square shape", name));
+        }
+        if !grid.iter().any(|row| row.iter().any(|&c| c != 0)) {
+            return Err(format!("piece {} has no blocks", name));
+        }
+        Ok(grid)
+    }
+}
+
+/// Parses an offset like `(-1,2)`, flipping the y axis so that it points down the board.
+fn parse_offset(text: &str) -> Option<Point> {
+    if !text.starts_with('(') || !text.ends_with(')') {
+        return None;
+    }
+    let parts: Vec<&str> = text[1..text.len() - 1].split(',').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    match (parts[0].trim().parse::<i32>(), parts[1].trim().parse::<i32>()) {
+        (Ok(x), Ok(y)) => Some(Point{ x, y: -y }),
+        _ => None,
+    }
+}
+
+/// The ways in which the sequence of pieces can be randomized.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Randomizer {
+    /// Deals out every piece in the set in a random order before starting on a new bag.
+    Bag,
+    /// The NES algorithm: pick any piece, but re-roll once if it repeats the previous piece.
+    Nes,
+    /// The TGM algorithm: pick any piece, re-rolling up to five times while it is one of the last four.
+    History,
+}
+
+impl Randomizer {
+    pub fn from_name(name: &str) -> Option<Randomizer> {
+        match name {
+            "bag" => Some(Randomizer::Bag),
+            "nes" => Some(Randomizer::Nes),
+            "history" => Some(Randomizer::History),
+            _ => None,
+        }
+    }
+}
+
+/// The most pieces after the current one that the game can preview.
+pub const MAX_PREVIEWS: usize = 6;
+
+/// Implements a queue of randomized pieces.
+///
+/// With the default `Randomizer::Bag`, instead of a purely random stream of piece types, this queue generates
+/// a random ordering of all possible types and ensures all of those pieces are used before re-generating a new
+/// random set. This helps avoid pathological cases where purely random generation provides the same piece type
+/// repeately in a row, or fails to provide a required piece for a very long time.
+#[derive(Clone)]
+pub struct PieceBag {
+    kinds: Vec<Arc<PieceDef>>,
+    randomizer: Randomizer,
+    rng: XorShiftRng,
+    /// The most recently dealt pieces, oldest first, as indices into `kinds`.
+    history: Vec<usize>,
+    pieces: Vec<Piece>
+}
+
+impl PieceBag {
+    /// Creates a bag which deals pieces from the set. Bags created with the same seed deal the same pieces.
+    pub fn new(set: &PieceSet, randomizer: Randomizer, seed: u64) -> PieceBag {
+        // The generator can't be seeded with all zeros, so the seed is mixed with some fixed bits.
+        let rng_seed = [seed as u32, (seed >> 32) as u32, 0x9e3779b9, 0x7f4a7c15];
+        let mut p = PieceBag{
+            kinds: set.pieces.clone(),
+            randomizer,
+            rng: XorShiftRng::from_seed(rng_seed),
+            history: Vec::new(),
+            pieces: Vec::new()
+        };
+        while p.pieces.len() < MAX_PREVIEWS {
+            p.fill_bag();
+        }
+        p
+    }
+
+    /// Removes and returns the next piece in the queue. The queue always holds enough pieces to show the longest
+    /// preview.
+    pub fn pop(&mut self) -> Piece {
+        let piece = self.pieces.remove(0);
+        while self.pieces.len() < MAX_PREVIEWS {
+            self.fill_bag();
+        }
+        piece
+    }
+
+    /// Returns a copy of the next piece in the queue.
+    pub fn peek(&self) -> Piece {
+        match self.pieces.first() {
+            Some(p) => p.clone(),
+            None => panic!("No next piece in piece bag")
+        }
+    }
+
+    /// Returns copies of the next `count` pieces in the queue, up to `MAX_PREVIEWS`.
+    pub fn preview(&self, count: usize) -> Vec<Piece> {
+        self.pieces.iter().take(cmp::min(count, MAX_PREVIEWS)).cloned().collect()
+    }
+
+    /// Adds more pieces to the queue according to the randomizer.
+    fn fill_bag(&mut self) {
+        match self.randomizer {
+            Randomizer::Bag => {
+                // Generate a random ordering of all possible pieces.
+                let mut pieces: Vec<Piece> = self.kinds.iter().map(Piece::new).collect();
+                while !pieces.is_empty() {
+                    let i = self.rng.gen::<usize>() % pieces.len();
+                    self.pieces.push(pieces.swap_remove(i));
+                }
+            },
+            Randomizer::Nes => {
+                // Roll one more than the number of pieces; rolling the extra value or repeating the previous
+                // piece causes a single re-roll which is accepted whatever it is.
+                let n = self.kinds.len();
+                let mut i = self.rng.gen_range(0, n + 1);
+                if i == n || self.history.last() == Some(&i) {
+                    i = self.rng.gen_range(0, n);
+                }
+                self.history = vec![i];
+                self.pieces.push(Piece::new(&self.kinds[i]));
+            },
+            Randomizer::History => {
+                let n = self.kinds.len();
+                let mut i = self.rng.gen_range(0, n);
+                for _ in 0..5 {
+                    if !self.history.contains(&i) {
+                        break;
+                    }
+                    i = self.rng.gen_range(0, n);
+                }
+                self.history.push(i);
+                if self.history.len() > 4 {
+                    self.history.remove(0);
+                }
+                self.pieces.push(Piece::new(&self.kinds[i]));
+            },
+        }
+    }
+}
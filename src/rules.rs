@@ -0,0 +1,457 @@
+use piece::{PieceSet, Randomizer};
+
+/// The number of game frames per second. All game timing is counted in frames.
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// The speed at which pieces fall: `rows` rows every `frames` frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gravity {
+    pub rows: u32,
+    pub frames: u32,
+}
+
+impl Gravity {
+    /// Returns true for 20G: gravity so strong that pieces fall to the stack the moment they appear or move.
+    pub fn is_instant(&self) -> bool {
+        self.rows >= 20 * self.frames
+    }
+
+    /// Converts a number of seconds per row, which may be less than one frame, into a gravity.
+    fn from_seconds(seconds: f64) -> Gravity {
+        let frames = seconds * FRAMES_PER_SECOND as f64;
+        if frames >= 1.0 {
+            Gravity{ rows: 1, frames: frames.round() as u32 }
+        } else {
+            Gravity{ rows: ::std::cmp::min((1.0 / frames).round() as u32, 20), frames: 1 }
+        }
+    }
+}
+
+/// Formats a number of frames as a time in minutes, seconds and hundredths, such as "2:05.50".
+pub fn format_time(frames: u32) -> String {
+    let hundredths = frames * 100 / FRAMES_PER_SECOND;
+    format!("{}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+}
+
+/// The delays between the phases of the game, in frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Delays {
+    /// The entry delay (ARE) between a piece locking and the next one appearing.
+    pub are: u32,
+    /// The delay while completed lines are cleared. This comes before the entry delay.
+    pub line_clear: u32,
+    /// How long a piece may rest on the stack before it locks. With no lock delay, a resting piece locks as
+    /// soon as gravity next pulls on it.
+    pub lock: u32,
+}
+
+/// How much of the locked stack the player can see.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StackVisibility {
+    Visible,
+    /// Locked blocks disappear this many frames after they lock.
+    Fading(u32),
+    /// Locked blocks disappear as soon as they lock.
+    Invisible,
+}
+
+impl StackVisibility {
+    pub fn from_name(name: &str) -> Option<StackVisibility> {
+        match name {
+            "visible" => Some(StackVisibility::Visible),
+            "fading" => Some(StackVisibility::Fading(5 * FRAMES_PER_SECOND)),
+            "invisible" => Some(StackVisibility::Invisible),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the player's finesse is checked: that each piece was moved into place with as few key presses as
+/// possible.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Finesse {
+    /// Finesse isn't checked.
+    Off,
+    /// Pieces placed with too many presses are counted as faults.
+    Count,
+    /// As well as being counted, a fault sends the piece back to the top to be placed again.
+    Practice,
+}
+
+impl Finesse {
+    pub fn from_name(name: &str) -> Option<Finesse> {
+        match name {
+            "off" => Some(Finesse::Off),
+            "count" => Some(Finesse::Count),
+            "practice" => Some(Finesse::Practice),
+            _ => None,
+        }
+    }
+}
+
+/// The kinds of T-spin a piece can lock with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+/// Describes how a piece locked: how many lines it cleared and whether it was a T-spin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LineClear {
+    pub lines: u32,
+    pub t_spin: TSpin,
+    /// Set when this clear continues a chain of difficult clears.
+    pub back_to_back: bool,
+}
+
+impl LineClear {
+    /// Returns true for the clears which can be chained back-to-back: four or more lines at once, or any
+    /// T-spin which clears lines.
+    pub fn is_difficult(&self) -> bool {
+        self.lines >= 4 || (self.lines > 0 && self.t_spin != TSpin::None)
+    }
+
+    /// Returns the number of rows of garbage the clear sends to an opponent by the guideline's attack table, with
+    /// one more for a back-to-back clear.
+    pub fn attack(&self) -> u32 {
+        let lines = match (self.t_spin, self.lines) {
+            (_, 0) => 0,
+            (TSpin::None, 1) => 0,
+            (TSpin::None, 2) => 1,
+            (TSpin::None, 3) => 2,
+            (TSpin::None, _) => 4,
+            (TSpin::Mini, 1) => 0,
+            (TSpin::Mini, _) => 1,
+            (TSpin::Full, lines) => lines * 2,
+        };
+        if self.back_to_back { lines + 1 } else { lines }
+    }
+
+    /// Returns a name for the clear to show the player, such as "T-Spin Double", if it is worth announcing.
+    pub fn description(&self) -> Option<String> {
+        let lines = match self.lines {
+            0 => "",
+            1 => " Single",
+            2 => " Double",
+            3 => " Triple",
+            _ => "",
+        };
+        let name = match self.t_spin {
+            TSpin::None if self.lines >= 4 => "Tetris".to_string(),
+            TSpin::None => return None,
+            TSpin::Mini => format!("T-Spin Mini{}", lines),
+            TSpin::Full => format!("T-Spin{}", lines),
+        };
+        if self.back_to_back {
+            Some(format!("B2B {}", name))
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// The game modes. A mode decides how the game speeds up, how it is scored, and which features the player may
+/// use.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// Modern guideline rules with an endless series of levels.
+    Marathon,
+    /// The rules of the 1989 NES game.
+    Nes,
+    /// The TGM arcade games' Master mode: gravity climbs to 20G and the delays shrink as the level goes from 0
+    /// to 999, while a hidden grade is kept.
+    Master,
+    /// A sandbox for practice, without gravity or game over, where placements can be undone.
+    Zen,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "marathon" => Some(Mode::Marathon),
+            "nes" => Some(Mode::Nes),
+            "master" => Some(Mode::Master),
+            "zen" => Some(Mode::Zen),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Marathon => "Marathon",
+            Mode::Nes => "NES",
+            Mode::Master => "Master",
+            Mode::Zen => "Zen",
+        }
+    }
+
+    /// Returns the default rules for this mode.
+    pub fn rules(&self) -> Rules {
+        match *self {
+            Mode::Marathon | Mode::Zen => Rules{
+                mode: *self,
+                piece_set: PieceSet::builtin("tetromino").unwrap(),
+                randomizer: Randomizer::Bag,
+                start_level: 1,
+                hold: true,
+                previews: 5,
+                hard_drop: true,
+                ghost: true,
+                rotate_180: true,
+                irs: true,
+                ihs: true,
+                move_reset: false,
+                stack: StackVisibility::Visible,
+                big: false,
+                finesse: Finesse::Off,
+                are: None,
+                line_clear_delay: None,
+            },
+            Mode::Nes => Rules{
+                mode: *self,
+                piece_set: PieceSet::builtin("tetromino-nes").unwrap(),
+                randomizer: Randomizer::Nes,
+                start_level: 0,
+                hold: false,
+                previews: 1,
+                hard_drop: false,
+                ghost: false,
+                rotate_180: false,
+                irs: false,
+                ihs: false,
+                move_reset: false,
+                stack: StackVisibility::Visible,
+                big: false,
+                finesse: Finesse::Off,
+                are: None,
+                line_clear_delay: None,
+            },
+            Mode::Master => Rules{
+                mode: *self,
+                piece_set: PieceSet::builtin("tetromino-ars").unwrap(),
+                randomizer: Randomizer::History,
+                start_level: 0,
+                hold: false,
+                previews: 1,
+                hard_drop: true,
+                ghost: false,
+                rotate_180: false,
+                irs: true,
+                ihs: false,
+                move_reset: false,
+                stack: StackVisibility::Visible,
+                big: false,
+                finesse: Finesse::Off,
+                are: None,
+                line_clear_delay: None,
+            },
+        }
+    }
+
+    /// Returns the gravity for the given level.
+    pub fn gravity(&self, level: u32) -> Gravity {
+        match *self {
+            Mode::Marathon => {
+                let level = level.clamp(1, 20) as f64;
+                Gravity::from_seconds((0.8 - (level - 1.0) * 0.007).powf(level - 1.0))
+            },
+            Mode::Nes => {
+                let frames = match level {
+                    0 => 48,
+                    1 => 43,
+                    2 => 38,
+                    3 => 33,
+                    4 => 28,
+                    5 => 23,
+                    6 => 18,
+                    7 => 13,
+                    8 => 8,
+                    9 => 6,
+                    10..=12 => 5,
+                    13..=15 => 4,
+                    16..=18 => 3,
+                    19..=28 => 2,
+                    _ => 1,
+                };
+                Gravity{ rows: 1, frames }
+            },
+            Mode::Master => {
+                // Measured in 256ths of a row per frame, reaching 20G at level 500.
+                let rows = match level {
+                    0..=29 => 4,
+                    30..=34 => 6,
+                    35..=39 => 8,
+                    40..=49 => 10,
+                    50..=59 => 12,
+                    60..=69 => 16,
+                    70..=79 => 32,
+                    80..=89 => 48,
+                    90..=99 => 64,
+                    100..=119 => 80,
+                    120..=139 => 96,
+                    140..=159 => 112,
+                    160..=169 => 128,
+                    170..=199 => 144,
+                    200..=219 => 4,
+                    220..=229 => 32,
+                    230..=232 => 64,
+                    233..=235 => 96,
+                    236..=238 => 128,
+                    239..=242 => 160,
+                    243..=246 => 192,
+                    247..=250 => 224,
+                    251..=299 => 256,
+                    300..=329 => 512,
+                    330..=359 => 768,
+                    360..=399 => 1024,
+                    400..=419 => 1280,
+                    420..=449 => 1024,
+                    450..=499 => 768,
+                    _ => 5120,
+                };
+                Gravity{ rows, frames: 256 }
+            },
+            Mode::Zen => Gravity{ rows: 0, frames: 1 },
+        }
+    }
+
+    /// Returns the delays between phases of the game at the given level.
+    pub fn delays(&self, level: u32) -> Delays {
+        match *self {
+            Mode::Marathon => Delays{ are: 0, line_clear: 0, lock: 0 },
+            Mode::Nes => Delays{ are: 10, line_clear: 18, lock: 0 },
+            Mode::Zen => Delays{ are: 0, line_clear: 20, lock: 0 },
+            Mode::Master => {
+                let (are, line_clear, lock) = match level {
+                    0..=499 => (25, 40, 30),
+                    500..=599 => (25, 25, 30),
+                    600..=699 => (25, 16, 30),
+                    700..=799 => (16, 12, 30),
+                    800..=899 => (12, 6, 30),
+                    _ => (12, 6, 17),
+                };
+                Delays{ are, line_clear, lock }
+            },
+        }
+    }
+
+    /// Returns the level reached after a piece locks at `level`, clearing `cleared` lines for a total of
+    /// `lines` lines since the starting level.
+    pub fn level_after_clear(&self, level: u32, start_level: u32, lines: u32, cleared: u32) -> u32 {
+        let next = match *self {
+            Mode::Marathon => start_level + lines / 10,
+            Mode::Nes => {
+                // The first level transition waits until more lines have been cleared when starting from a
+                // higher level; after that the level goes up every ten lines.
+                let first = ::std::cmp::min(start_level * 10 + 10,
+                                            ::std::cmp::max(100, (start_level * 10).saturating_sub(50)));
+                if lines < first {
+                    start_level
+                } else {
+                    start_level + 1 + (lines - first) / 10
+                }
+            },
+            Mode::Master => ::std::cmp::min(level + cleared, 999),
+            Mode::Zen => level,
+        };
+        ::std::cmp::max(level, next)
+    }
+
+    /// Returns the level reached when a new piece appears at `level`. Only Master mode counts pieces, and
+    /// each hundred levels (and the final level) can only be entered by clearing lines.
+    pub fn level_after_spawn(&self, level: u32) -> u32 {
+        match *self {
+            Mode::Master if level % 100 != 99 && level < 998 => level + 1,
+            _ => level,
+        }
+    }
+
+    /// Returns false for modes in which the game never ends by topping out. The stack is cleared instead.
+    pub fn can_top_out(&self) -> bool {
+        *self != Mode::Zen
+    }
+
+    /// Returns true for modes in which placements can be undone.
+    pub fn allows_undo(&self) -> bool {
+        *self == Mode::Zen
+    }
+
+    /// Returns the level at which the game is won, if it has an end.
+    pub fn final_level(&self) -> Option<u32> {
+        match *self {
+            Mode::Master => Some(999),
+            _ => None,
+        }
+    }
+
+    /// Returns the points awarded when a piece locks.
+    pub fn clear_score(&self, clear: &LineClear, level: u32) -> u32 {
+        let lines = ::std::cmp::min(clear.lines, 4) as usize;
+        match *self {
+            Mode::Marathon | Mode::Zen => {
+                let points = match clear.t_spin {
+                    TSpin::None => [0, 100, 300, 500, 800][lines],
+                    TSpin::Mini => [100, 200, 400, 400, 400][lines],
+                    TSpin::Full => [400, 800, 1200, 1600, 1600][lines],
+                };
+                if clear.back_to_back {
+                    points * 3 / 2 * level
+                } else {
+                    points * level
+                }
+            },
+            // The NES game didn't know about T-spins.
+            Mode::Nes => [0, 40, 100, 300, 1200][lines] * (level + 1),
+            Mode::Master => (level + clear.lines).div_ceil(4) * clear.lines,
+        }
+    }
+
+    /// Returns the points awarded for each row a piece is soft dropped.
+    pub fn soft_drop_score(&self) -> u32 {
+        1
+    }
+
+    /// Returns the points awarded for each row a piece is hard dropped.
+    pub fn hard_drop_score(&self) -> u32 {
+        2
+    }
+}
+
+/// The complete set of rules a game is played with. These start as the defaults for a mode, but can be
+/// adjusted from the command line.
+#[derive(Clone)]
+pub struct Rules {
+    pub mode: Mode,
+    pub piece_set: PieceSet,
+    pub randomizer: Randomizer,
+    pub start_level: u32,
+    /// Whether the player may hold a piece for later.
+    pub hold: bool,
+    /// The number of pieces after the current one that are shown, up to `MAX_PREVIEWS`.
+    pub previews: usize,
+    /// Whether the player may hard drop pieces.
+    pub hard_drop: bool,
+    /// Whether a ghost piece shows where the current piece will land.
+    pub ghost: bool,
+    /// Whether the player may rotate pieces by 180 degrees in one step.
+    pub rotate_180: bool,
+    /// Whether holding a rotation key as a piece spawns rotates it before it appears.
+    pub irs: bool,
+    /// Whether holding the hold key as a piece spawns holds it before it appears.
+    pub ihs: bool,
+    /// Whether moving or rotating a piece resting on the stack restarts its lock delay. Otherwise the lock
+    /// delay only restarts when the piece falls a row.
+    pub move_reset: bool,
+    /// How much of the stack is drawn. Challenge modes hide it, revealing it only when the game ends.
+    pub stack: StackVisibility,
+    /// Whether pieces are played at double size, two cells to a block, on a field of half the resolution.
+    pub big: bool,
+    /// Whether finesse is checked: not at all, by counting faults, or by also making faulty pieces be replaced.
+    pub finesse: Finesse,
+    /// Overrides the mode's entry delay, in frames.
+    pub are: Option<u32>,
+    /// Overrides the mode's line clear delay, in frames.
+    pub line_clear_delay: Option<u32>,
+}
@@ -0,0 +1,170 @@
+use ai::{HeuristicPlanner, Planner, Weights};
+use game::{Action, Game, GameOver};
+use json::Json;
+use rules::{LineClear, Rules, TSpin};
+use std::collections::BTreeMap;
+
+/// The outcome of a game played by the AI without a display.
+pub struct GameResult {
+    pub lines: u32,
+    pub pieces: u32,
+    pub score: u32,
+    /// Set if the game ended by topping out, rather than by being completed or reaching the piece limit.
+    pub topped_out: bool,
+    /// The number of times each kind of clear was made, by name.
+    pub clears: BTreeMap<&'static str, u32>,
+}
+
+/// Plays a game with the AI as fast as it can be played, stopping when the game ends or `max_pieces` pieces
+/// have locked. Each piece is moved straight to its placement between frames, but the delays between pieces
+/// run frame by frame, so a game with the same rules, seed and weights always plays out the same way.
+pub fn play(rules: Rules, seed: u64, weights: Weights, max_pieces: u32) -> GameResult {
+    let drop = if rules.hard_drop { Action::HardDrop } else { Action::SoftDrop };
+    let mut game = Game::with_seed(rules, seed);
+    let mut planner = HeuristicPlanner::new(weights);
+    let mut clears = BTreeMap::new();
+
+    while game.game_over().is_none() && game.pieces() < max_pieces {
+        if game.current_piece().is_none() {
+            game.update();
+            continue;
+        }
+
+        let pieces = game.pieces();
+        let path = planner.plan(&game).and_then(|target| game.find_path(&target)).unwrap_or_default();
+        for action in path {
+            game.perform(action);
+            if game.pieces() != pieces {
+                break;
+            }
+        }
+        // If the path went wrong, drop the piece wherever it is. Each drop either moves it down or locks it.
+        while game.pieces() == pieces && game.game_over().is_none() {
+            game.perform(drop);
+        }
+
+        if let Some(name) = game.last_lock().and_then(|clear| clear_name(&clear)) {
+            *clears.entry(name).or_insert(0) += 1;
+        }
+    }
+
+    GameResult{
+        lines: game.lines(),
+        pieces: game.pieces(),
+        score: game.score(),
+        topped_out: game.game_over().is_some_and(|reason| reason != GameOver::Completed),
+        clears,
+    }
+}
+
+/// Returns the name a clear is counted under, or `None` for a piece which made no clear or T-spin.
+fn clear_name(clear: &LineClear) -> Option<&'static str> {
+    match (clear.t_spin, clear.lines) {
+        (TSpin::None, 0) => None,
+        (TSpin::None, 1) => Some("single"),
+        (TSpin::None, 2) => Some("double"),
+        (TSpin::None, 3) => Some("triple"),
+        (TSpin::None, _) => Some("tetris"),
+        (TSpin::Mini, 0) => Some("t_spin_mini"),
+        (TSpin::Mini, 1) => Some("t_spin_mini_single"),
+        (TSpin::Mini, _) => Some("t_spin_mini_double"),
+        (TSpin::Full, 0) => Some("t_spin"),
+        (TSpin::Full, 1) => Some("t_spin_single"),
+        (TSpin::Full, 2) => Some("t_spin_double"),
+        (TSpin::Full, _) => Some("t_spin_triple"),
+    }
+}
+
+/// Totals up the results of many games.
+#[derive(Default)]
+pub struct Summary {
+    pub games: u32,
+    pub lines: u64,
+    pub pieces: u64,
+    pub score: u64,
+    pub top_outs: u32,
+    pub clears: BTreeMap<&'static str, u32>,
+}
+
+impl Summary {
+    pub fn add(&mut self, result: &GameResult) {
+        self.games += 1;
+        self.lines += result.lines as u64;
+        self.pieces += result.pieces as u64;
+        self.score += result.score as u64;
+        if result.topped_out {
+            self.top_outs += 1;
+        }
+        for (&name, &count) in &result.clears {
+            *self.clears.entry(name).or_insert(0) += count;
+        }
+    }
+
+    pub fn average_lines(&self) -> f64 {
+        self.lines as f64 / ::std::cmp::max(self.games, 1) as f64
+    }
+
+    pub fn to_json(&self) -> Json {
+        let games = ::std::cmp::max(self.games, 1) as f64;
+        let clears = self.clears.iter().map(|(&name, &count)| (name.to_string(), count.into())).collect();
+        Json::object(vec![
+            ("games", self.games.into()),
+            ("average_lines", self.average_lines().into()),
+            ("average_pieces", (self.pieces as f64 / games).into()),
+            ("average_score", (self.score as f64 / games).into()),
+            ("top_outs", self.top_outs.into()),
+            ("clears", Json::Object(clears)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ai::DEFAULT_WEIGHTS;
+    use rules::Mode;
+
+    fn summarize(seeds: ::std::ops::Range<u64>, max_pieces: u32) -> Summary {
+        let mut summary = Summary::default();
+        for seed in seeds {
+            summary.add(&play(Mode::Marathon.rules(), seed, DEFAULT_WEIGHTS, max_pieces));
+        }
+        summary
+    }
+
+    #[test]
+    fn same_seeds_and_weights_play_the_same_games() {
+        assert_eq!(summarize(0..2, 40).to_json(), summarize(0..2, 40).to_json());
+    }
+
+    #[test]
+    fn games_stop_at_the_piece_limit() {
+        let result = play(Mode::Marathon.rules(), 0, DEFAULT_WEIGHTS, 20);
+        assert_eq!(result.pieces, 20);
+        assert!(!result.topped_out);
+    }
+
+    #[test]
+    fn clears_are_counted_under_their_names() {
+        let clear = |lines, t_spin| LineClear{ lines, t_spin, back_to_back: false };
+        let mut result = play(Mode::Marathon.rules(), 0, DEFAULT_WEIGHTS, 0);
+        for made in &[clear(0, TSpin::None), clear(4, TSpin::None), clear(2, TSpin::Full), clear(1, TSpin::Mini)] {
+            if let Some(name) = clear_name(made) {
+                *result.clears.entry(name).or_insert(0) += 1;
+            }
+        }
+        let mut summary = Summary::default();
+        summary.add(&result);
+
+        let json = summary.to_json();
+        for key in &["games", "average_lines", "average_pieces", "average_score", "top_outs"] {
+            assert!(json.get(key).is_some(), "missing {}", key);
+        }
+        let clears = json.get("clears").unwrap();
+        assert_eq!(*clears, Json::object(vec![
+            ("t_spin_double", 1.into()),
+            ("t_spin_mini_single", 1.into()),
+            ("tetris", 1.into()),
+        ]));
+    }
+}
@@ -0,0 +1,292 @@
+use ai::Placement;
+use board::*;
+use display::Display;
+use game::Game;
+use json::Json;
+use net::{board_to_json, color_code, color_from_code, read_board, read_messages};
+use piece::{Piece, PieceSet, MAX_PREVIEWS};
+use rules::StackVisibility;
+use std::cmp;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use util::*;
+use versus::PLAYER_WIDTH;
+
+/// The most players a spectator's screen has room for.
+const MAX_PLAYERS: usize = 2;
+
+/// Holds the next state to be written to a spectator. It holds only one: a newer state replaces one which
+/// hasn't been written yet, so a spectator who falls behind skips ahead to the latest instead of piling up
+/// states waiting for them.
+struct Mailbox {
+    state: Mutex<Option<String>>,
+    ready: Condvar,
+    /// Set once the spectator has gone.
+    closed: AtomicBool,
+}
+
+impl Mailbox {
+    fn new(state: Option<String>) -> Mailbox {
+        Mailbox{ state: Mutex::new(state), ready: Condvar::new(), closed: AtomicBool::new(false) }
+    }
+
+    fn post(&self, state: &str) {
+        *self.state.lock().unwrap() = Some(state.to_string());
+        self.ready.notify_one();
+    }
+
+    /// Waits for a state to be posted, and takes it.
+    fn take(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(state) = state.take() {
+                return state;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+}
+
+/// Streams a game to spectators connected to a local port, as a line of JSON describing the state of each
+/// player's game each time it changes. Each spectator is written to on its own thread, so a slow one never
+/// holds up the game.
+pub struct Broadcaster {
+    spectators: Arc<Mutex<Vec<Arc<Mailbox>>>>,
+    /// The last state sent, which new spectators are sent first so they don't start with an empty screen.
+    last: Arc<Mutex<Option<String>>>,
+}
+
+impl Broadcaster {
+    /// Starts listening for spectators on `port` of the local machine.
+    pub fn listen(port: u16) -> Result<Broadcaster, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|err| format!("Couldn't listen for spectators on port {}: {}", port, err))?;
+        let spectators = Arc::new(Mutex::new(Vec::new()));
+        let last: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        {
+            let spectators = spectators.clone();
+            let last = last.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let mailbox = Arc::new(Mailbox::new(last.lock().unwrap().clone()));
+                    spectators.lock().unwrap().push(mailbox.clone());
+                    thread::spawn(move || {
+                        loop {
+                            if writeln!(stream, "{}", mailbox.take()).is_err() {
+                                mailbox.closed.store(true, Ordering::SeqCst);
+                                break;
+                            }
+                        }
+                    });
+                }
+            });
+        }
+        Ok(Broadcaster{ spectators, last })
+    }
+
+    /// Sends the state of each player's game, as described by `game_state`, to every spectator, forgetting
+    /// those who have gone.
+    pub fn send(&self, players: Vec<Json>) {
+        let line = Json::object(vec![("type", "state".into()), ("players", players.into())]).to_string();
+        let mut spectators = self.spectators.lock().unwrap();
+        spectators.retain(|mailbox| !mailbox.closed.load(Ordering::SeqCst));
+        for mailbox in spectators.iter() {
+            mailbox.post(&line);
+        }
+        *self.last.lock().unwrap() = Some(line);
+    }
+}
+
+/// Describes the state of a game for spectators: its board, with a letter for the color of each block as
+/// written by `board_to_json`, the cells of the current piece, the pieces in the queue and in hold, and the
+/// score.
+pub fn game_state(game: &Game) -> Json {
+    let current = game.current_piece().map(|(piece, position)| {
+        let placement = Placement{ piece: piece.clone(), position, hold: false };
+        let cells: Vec<Json> = placement.cells().iter()
+            .map(|cell| Json::Array(vec![cell.x.into(), cell.y.into()]))
+            .collect();
+        Json::object(vec![
+            ("piece", piece.name().into()),
+            ("color", color_code(piece.color()).to_string().into()),
+            ("cells", cells.into()),
+        ])
+    });
+    let queue: Vec<Json> = game.queue().iter().map(|piece| piece.name().into()).collect();
+    Json::object(vec![
+        ("mode", game.rules().mode.name().into()),
+        ("pieces", game.rules().piece_set.name.as_str().into()),
+        ("board", board_to_json(game.board())),
+        ("current", current.into()),
+        ("queue", queue.into()),
+        ("hold", game.held_piece().map(|piece| piece.name()).into()),
+        ("score", game.score().into()),
+        ("level", game.level().into()),
+        ("lines", game.lines().into()),
+        ("game_over", game.game_over().map(|reason| reason.description()).into()),
+    ])
+}
+
+/// Describes one player's game in a battle, as `game_state` does, along with their name and the garbage
+/// waiting for them.
+pub fn player_state(name: &str, game: &Game, incoming: u32) -> Json {
+    let mut state = game_state(game);
+    if let Json::Object(ref mut members) = state {
+        members.insert(0, ("name".to_string(), name.into()));
+        members.push(("incoming".to_string(), incoming.into()));
+    }
+    state
+}
+
+/// Watches a game streamed by a `Broadcaster`, drawing each player's game side by side as the states arrive.
+/// Nothing can be done to the game from here.
+pub struct Spectator {
+    messages: Receiver<Json>,
+    connected: bool,
+    /// The state of each player's game last received, and their boards.
+    players: Vec<Json>,
+    boards: Vec<Board>,
+    /// The name of the piece set the game is played with, and the set itself if it's a built-in one, for
+    /// drawing the queue and hold.
+    piece_set_name: String,
+    piece_set: Option<PieceSet>,
+}
+
+impl Spectator {
+    /// Connects to a game being broadcast at `address`, given as `<host>:<port>`.
+    pub fn connect(address: &str) -> Result<Spectator, String> {
+        let stream = TcpStream::connect(address)
+            .map_err(|err| format!("Couldn't connect to {}: {}", address, err))?;
+        Ok(Spectator{
+            messages: read_messages(stream),
+            connected: true,
+            players: Vec::new(),
+            boards: Vec::new(),
+            piece_set_name: String::new(),
+            piece_set: None,
+        })
+    }
+
+    /// Takes in whatever states have arrived. Returns true if there's anything new to draw.
+    pub fn update(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => {
+                    if message.get("type").and_then(Json::as_str) == Some("state") {
+                        let players = message.get("players").and_then(Json::as_array).unwrap_or(&[]);
+                        self.players = players.iter().take(MAX_PLAYERS).cloned().collect();
+                        self.boards = self.players.iter()
+                            .map(|player| {
+                                let mut board = Board::new();
+                                read_board(player.get("board").unwrap_or(&Json::Null), &mut board);
+                                board
+                            })
+                            .collect();
+                        let pieces = self.players.first().and_then(|player| player.get("pieces"))
+                            .and_then(Json::as_str)
+                            .unwrap_or("");
+                        if self.piece_set_name != pieces {
+                            self.piece_set_name = pieces.to_string();
+                            // Only built-in sets are looked up, so the stream can't choose files to be read.
+                            self.piece_set = PieceSet::builtin(pieces);
+                        }
+                        changed = true;
+                    }
+                },
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => {
+                    changed = changed || self.connected;
+                    self.connected = false;
+                    return changed;
+                },
+            }
+        }
+    }
+
+    pub fn render(&self, display: &mut Display) {
+        if self.players.is_empty() {
+            let status = if self.connected { "Waiting for the game..." } else { "Disconnected" };
+            Board::new().render(display, StackVisibility::Visible, 0);
+            display.set_text(status, BOARD_WIDTH * 2 + 5, 20, Color::Red, Color::Black);
+            return;
+        }
+        for (i, (state, board)) in self.players.iter().zip(&self.boards).enumerate() {
+            display.set_origin(i as u32 * PLAYER_WIDTH);
+            self.render_player(display, state, board);
+        }
+        display.set_origin(0);
+    }
+
+    fn render_player(&self, display: &mut Display, state: &Json, board: &Board) {
+        let left_margin = BOARD_WIDTH * 2 + 5;
+        board.render(display, StackVisibility::Visible, 0);
+        let text = |key: &str| state.get(key).and_then(Json::as_str);
+        let number = |key: &str| state.get(key).and_then(Json::as_i64).unwrap_or(0);
+        if let Some(current) = state.get("current") {
+            let color = current.get("color").and_then(Json::as_str).and_then(|code| code.chars().next())
+                .and_then(color_from_code)
+                .unwrap_or(Color::Gray);
+            for cell in current.get("cells").and_then(Json::as_array).unwrap_or(&[]) {
+                let point = cell.as_array().unwrap_or(&[]);
+                let (x, y) = match (point.first().and_then(Json::as_i64), point.get(1).and_then(Json::as_i64)) {
+                    (Some(x), Some(y)) if x >= 0 && x < BOARD_WIDTH as i64 => (x as u32, y as i32),
+                    _ => continue,
+                };
+                if let Some(y) = Board::screen_row(y) {
+                    display.set_text("  ", 1 + 2 * x, y, color, color);
+                }
+            }
+        }
+
+        display.set_text(text("mode").unwrap_or(""), left_margin, 0, Color::Red, Color::Black);
+        display.set_text(text("name").unwrap_or("Spectating"), left_margin, 1, Color::Red, Color::Black);
+        display.set_text(format!("Score: {}", number("score")), left_margin, 2, Color::Red, Color::Black);
+        display.set_text(format!("Level: {}", number("level")), left_margin, 3, Color::Red, Color::Black);
+        display.set_text(format!("Lines: {}", number("lines")), left_margin, 4, Color::Red, Color::Black);
+
+        let queue = state.get("queue").and_then(Json::as_array).unwrap_or(&[]);
+        let queue = &queue[..cmp::min(queue.len(), MAX_PREVIEWS)];
+        if !queue.is_empty() {
+            let label = if queue.len() > 1 { "Next pieces:" } else { "Next piece:" };
+            display.set_text(label, left_margin, 6, Color::Red, Color::Black);
+        }
+        for (i, name) in queue.iter().enumerate() {
+            let origin = Point{ x: left_margin as i32 + 2 + 10 * i as i32, y: 7 };
+            self.render_piece(display, name.as_str().unwrap_or(""), origin);
+        }
+        if let Some(name) = text("hold") {
+            display.set_text("Hold:", left_margin, 13, Color::Red, Color::Black);
+            self.render_piece(display, name, Point{ x: left_margin as i32 + 2, y: 14 });
+        }
+
+        if number("incoming") > 0 {
+            let incoming = format!("Incoming garbage: {}", number("incoming"));
+            display.set_text(&incoming, left_margin, 16, Color::Red, Color::Black);
+        }
+
+        if let Some(reason) = text("game_over") {
+            display.set_text("Game over!", left_margin, 20, Color::Red, Color::Black);
+            display.set_text(reason, left_margin, 21, Color::Red, Color::Black);
+        } else if !self.connected {
+            display.set_text("Disconnected", left_margin, 20, Color::Red, Color::Black);
+        }
+    }
+
+    /// Draws a piece from the queue or hold, or its name if the piece set couldn't be found.
+    fn render_piece(&self, display: &mut Display, name: &str, origin: Point) {
+        let def = self.piece_set.as_ref().and_then(|set| set.pieces.iter().find(|def| def.name == name));
+        match def {
+            Some(def) => Game::render_preview(display, &Piece::new(def), origin),
+            None => display.set_text(name, origin.x as u32, origin.y as u32, Color::Red, Color::Black),
+        }
+    }
+}
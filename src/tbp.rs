@@ -0,0 +1,286 @@
+use ai::{Placement, Planner};
+use board::*;
+use game::Game;
+use json::Json;
+use piece::{Piece, Randomizer};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+use util::*;
+
+/// How long to wait for a bot to introduce itself and accept the rules before giving up on it.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// How long to give a bot to exit after being told to quit before killing it.
+const QUIT_TIMEOUT_MS: u64 = 1000;
+
+/// The names TBP gives the orientations of a piece, in clockwise order from the spawn orientation.
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+/// Returns the cells of a tetromino facing north, relative to its centre with y pointing up, as TBP places
+/// them. Returns `None` for pieces TBP doesn't know.
+fn north_cells(name: &str) -> Option<[(i32, i32); 4]> {
+    match name {
+        "I" => Some([(-1, 0), (0, 0), (1, 0), (2, 0)]),
+        "O" => Some([(0, 0), (1, 0), (0, 1), (1, 1)]),
+        "T" => Some([(-1, 0), (0, 0), (1, 0), (0, 1)]),
+        "L" => Some([(-1, 0), (0, 0), (1, 0), (1, 1)]),
+        "J" => Some([(-1, 0), (0, 0), (1, 0), (-1, 1)]),
+        "S" => Some([(-1, 0), (0, 0), (0, 1), (1, 1)]),
+        "Z" => Some([(-1, 1), (0, 1), (0, 0), (1, 0)]),
+        _ => None,
+    }
+}
+
+/// Plays a game with an external bot speaking the Tetris Bot Protocol.
+///
+/// The bot runs as a child process, reading one JSON message a line from its standard input and writing its
+/// replies to its standard output. It is told about the game with a `start` message, asked for a `suggest`ion
+/// for each piece, and told about each move played and each piece added to the queue, so that it can keep
+/// thinking ahead. If the game goes somewhere the bot didn't expect, as it does after undo, the bot is stopped
+/// and started again from the game's state.
+pub struct TbpBot {
+    /// The name the bot gave for itself.
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Json>,
+    /// Set once the bot has been sent `start`, and while it is thinking about a suggestion.
+    started: bool,
+    waiting: bool,
+    /// The number of pieces locked when the last suggestion was asked for.
+    pieces: u32,
+    /// The move taken from the last suggestion, and the number of pieces left in the bot's queue once it is
+    /// played. The bot's queue starts with the current piece, followed by the pieces in the preview.
+    played: Option<(Json, usize)>,
+}
+
+impl TbpBot {
+    /// Runs `command` as a bot for `game`, and waits for it to be ready to play.
+    pub fn start(command: &str, game: &Game) -> Result<TbpBot, String> {
+        let rules = game.rules();
+        if rules.big || !rules.piece_set.pieces.iter().all(|def| north_cells(&def.name).is_some()) {
+            return Err("Bots can only play with the standard tetrominoes at normal size".to_string());
+        }
+
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| "No bot command given".to_string())?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("Couldn't run bot '{}': {}", command, err))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // Read the bot's messages on a thread of their own so that the game never waits on the bot.
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let message = match line {
+                    Ok(line) => Json::parse(&line),
+                    Err(_) => break,
+                };
+                if let Ok(message) = message {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut bot = TbpBot{
+            name: String::new(),
+            child,
+            stdin,
+            messages: rx,
+            started: false,
+            waiting: false,
+            pieces: 0,
+            played: None,
+        };
+
+        let info = bot.receive("info")?;
+        bot.name = info.get("name").and_then(Json::as_str).unwrap_or(program).to_string();
+        let randomizer = if rules.piece_set.pieces.len() == 7 && rules.randomizer == Randomizer::Bag {
+            "seven_bag"
+        } else {
+            "unknown"
+        };
+        bot.send(Json::object(vec![("type", "rules".into()), ("randomizer", randomizer.into())]));
+        bot.receive("ready")?;
+        Ok(bot)
+    }
+
+    /// Waits for a message of the given type from the bot during the handshake.
+    fn receive(&self, kind: &str) -> Result<Json, String> {
+        let timeout = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+        let message = self.messages.recv_timeout(timeout)
+            .map_err(|_| format!("The bot didn't send '{}'", kind))?;
+        match message.get("type").and_then(Json::as_str) {
+            Some(t) if t == kind => Ok(message),
+            Some("error") => {
+                let reason = message.get("reason").and_then(Json::as_str).unwrap_or("unknown");
+                Err(format!("The bot refused to play: {}", reason))
+            },
+            _ => Err(format!("Expected '{}' from the bot but got {}", kind, message)),
+        }
+    }
+
+    /// Sends a message to the bot. A bot which has gone away simply stops answering, so errors are ignored.
+    fn send(&mut self, message: Json) {
+        let _ = writeln!(self.stdin, "{}", message);
+        let _ = self.stdin.flush();
+    }
+
+    fn send_new_piece(&mut self, piece: &Piece) {
+        self.send(Json::object(vec![("type", "new_piece".into()), ("piece", piece.name().into())]));
+    }
+
+    /// Describes the game to the bot from scratch.
+    fn send_start(&mut self, game: &Game, piece: &Piece) {
+        // TBP lists the rows of the board from the bottom up. The colors of locked blocks aren't kept, so
+        // they're all sent as garbage.
+        let board = game.board().cells.iter().rev()
+            .map(|row| Json::Array(row.iter().map(|cell| cell.map(|_| "G").into()).collect()))
+            .collect();
+        let queue = Some(piece.clone()).into_iter().chain(game.queue()).map(|p| p.name().into()).collect();
+        self.send(Json::object(vec![
+            ("type", "start".into()),
+            ("hold", game.held_piece().map(|p| p.name()).into()),
+            ("queue", Json::Array(queue)),
+            ("combo", game.combo().into()),
+            ("back_to_back", game.back_to_back().into()),
+            ("board", Json::Array(board)),
+        ]));
+    }
+}
+
+impl Drop for TbpBot {
+    /// Asks the bot to quit, and waits for it to exit so that it doesn't linger as a zombie process. A bot
+    /// which doesn't quit in time is killed.
+    fn drop(&mut self) {
+        self.send(Json::object(vec![("type", "quit".into())]));
+        let poll = Duration::from_millis(10);
+        for _ in 0..(QUIT_TIMEOUT_MS / 10) {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(poll);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Planner for TbpBot {
+    fn plan(&mut self, game: &Game) -> Option<Placement> {
+        let piece = match game.current_piece() {
+            Some((piece, _)) => piece.clone(),
+            None => return None,
+        };
+
+        if !self.waiting {
+            // Carry on from the last move if it was played as expected, or start over if it wasn't.
+            match self.played.take() {
+                Some((mv, known)) if game.pieces() == self.pieces + 1 => {
+                    self.send(Json::object(vec![("type", "play".into()), ("move", mv)]));
+                    let queue: Vec<Piece> = Some(piece.clone()).into_iter().chain(game.queue()).collect();
+                    for new_piece in queue.iter().skip(known) {
+                        self.send_new_piece(new_piece);
+                    }
+                },
+                _ => {
+                    if self.started {
+                        self.send(Json::object(vec![("type", "stop".into())]));
+                    }
+                    self.send_start(game, &piece);
+                    self.started = true;
+                },
+            }
+            self.send(Json::object(vec![("type", "suggest".into())]));
+            self.waiting = true;
+            self.pieces = game.pieces();
+        }
+
+        let message = match self.messages.try_recv() {
+            Ok(message) => message,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => return Some(drop_in_place(game, &piece)),
+        };
+        if message.get("type").and_then(Json::as_str) != Some("suggestion") {
+            return None;
+        }
+        self.waiting = false;
+        if game.pieces() != self.pieces {
+            // The suggestion was for a piece which has since locked.
+            return None;
+        }
+
+        let moves = message.get("moves").and_then(Json::as_array).unwrap_or(&[]);
+        for mv in moves {
+            if let Some(placement) = mv.get("location").and_then(|location| location_placement(game, location)) {
+                // Playing the move uses up the current piece, and the next as well if hold was empty.
+                let used = if placement.hold && game.held_piece().is_none() { 2 } else { 1 };
+                let known = (1 + game.queue().len()).saturating_sub(used);
+                self.played = Some((mv.clone(), known));
+                return Some(placement);
+            }
+        }
+        Some(drop_in_place(game, &piece))
+    }
+}
+
+/// Returns the placement which drops the current piece straight down, for when the bot has nothing to offer.
+fn drop_in_place(game: &Game, piece: &Piece) -> Placement {
+    Placement{ piece: piece.clone(), position: game.find_dropped_position(), hold: false }
+}
+
+/// Finds the placement of the current piece, or the piece hold would swap in, which covers the cells of a TBP
+/// piece location. Returns `None` if the location can't be played.
+fn location_placement(game: &Game, location: &Json) -> Option<Placement> {
+    let name = location.get("type").and_then(Json::as_str)?;
+    let orientation = location.get("orientation").and_then(Json::as_str)?;
+    let orientation = ORIENTATIONS.iter().position(|&o| o == orientation)?;
+    let x = location.get("x").and_then(Json::as_i64)? as i32;
+    let y = location.get("y").and_then(Json::as_i64)? as i32;
+
+    let (current, _) = game.current_piece()?;
+    let (mut piece, hold) = if current.name() == name {
+        (current.clone(), false)
+    } else {
+        let swapped = game.held_piece().cloned().unwrap_or_else(|| game.next_piece());
+        if !game.rules().hold || swapped.name() != name {
+            return None;
+        }
+        (swapped, true)
+    };
+
+    // Turn the piece's cells clockwise into the orientation, and then into board coordinates, where y points
+    // down.
+    let mut target: Vec<Point> = north_cells(name)?.iter()
+        .map(|&cell| {
+            let (cx, cy) = (0..orientation).fold(cell, |(cx, cy), _| (cy, -cx));
+            Point{ x: x + cx, y: BOARD_HEIGHT as i32 - 1 - (y + cy) }
+        })
+        .collect();
+    target.sort_by_key(|p| (p.y, p.x));
+
+    // Find the rotation state of the piece with the same shape, and the position that puts it over the cells.
+    piece.reset_rotation();
+    for _ in 0..4 {
+        let mut cells = Vec::new();
+        piece.each_point(&mut |row, col| cells.push(Point{ x: col, y: row }));
+        cells.sort_by_key(|p| (p.y, p.x));
+        let offset = Point{ x: target[0].x - cells[0].x, y: target[0].y - cells[0].y };
+        if cells.iter().zip(&target).all(|(c, t)| c.x + offset.x == t.x && c.y + offset.y == t.y) {
+            return Some(Placement{ piece, position: offset, hold });
+        }
+        piece.rotate(Direction::Right);
+    }
+    None
+}
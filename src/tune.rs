@@ -0,0 +1,187 @@
+use ai::{Weights, DEFAULT_WEIGHTS};
+use json::Json;
+use rand::distributions::{IndependentSample, Normal};
+use rand::{SeedableRng, XorShiftRng};
+use rules::Rules;
+use sim;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// The spread of the weights tried in the first generation.
+const INITIAL_DEVIATION: f64 = 0.5;
+
+/// Extra spread added to the weights of each generation, shrinking as the search goes on, which stops the
+/// search from settling too soon on the first good weights it finds.
+const NOISE: f64 = 0.1;
+
+/// How the weights are tuned.
+#[derive(Clone)]
+pub struct Settings {
+    pub rules: Rules,
+    /// The seeds of the games each candidate plays, including the first but not the last. Every candidate
+    /// plays the same games, so that they're compared fairly.
+    pub seeds: (u64, u64),
+    /// The number of pieces after which a game is stopped.
+    pub max_pieces: u32,
+    /// The number of candidates tried in each generation, and the number of the best of them that the next
+    /// generation is drawn from.
+    pub population: usize,
+    pub elite: usize,
+    pub threads: usize,
+}
+
+/// The state of a search for the best weights by the cross-entropy method: each weight is drawn from a normal
+/// distribution, and after each generation the distributions are fitted to the best candidates. The state is
+/// saved to a checkpoint file after every generation so that the search can be resumed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Population {
+    pub generation: u32,
+    pub mean: [f64; 5],
+    pub deviation: [f64; 5],
+    /// The best weights found so far, and the average lines they cleared.
+    pub best: Weights,
+    pub best_lines: f64,
+}
+
+impl Population {
+    /// Starts a search around the built-in weights.
+    pub fn new() -> Population {
+        Population{
+            generation: 0,
+            mean: to_array(&DEFAULT_WEIGHTS),
+            deviation: [INITIAL_DEVIATION; 5],
+            best: DEFAULT_WEIGHTS,
+            best_lines: 0.0,
+        }
+    }
+
+    /// Reads a population saved by `save`.
+    pub fn load(path: &str) -> Result<Population, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+        let json = Json::parse(&text).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+        let array = |name: &str| -> Result<[f64; 5], String> {
+            let items = json.get(name).and_then(Json::as_array).unwrap_or(&[]);
+            let values: Vec<f64> = items.iter().filter_map(Json::as_f64).collect();
+            if values.len() != 5 {
+                return Err(format!("Expected five numbers for '{}' in {}", name, path));
+            }
+            Ok([values[0], values[1], values[2], values[3], values[4]])
+        };
+        Ok(Population{
+            generation: json.get("generation").and_then(Json::as_i64).unwrap_or(0) as u32,
+            mean: array("mean")?,
+            deviation: array("deviation")?,
+            best: Weights::from_json(json.get("best").unwrap_or(&Json::Null))?,
+            best_lines: json.get("best_lines").and_then(Json::as_f64).unwrap_or(0.0),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let array = |values: &[f64; 5]| Json::Array(values.iter().map(|&v| v.into()).collect());
+        let json = Json::object(vec![
+            ("generation", self.generation.into()),
+            ("mean", array(&self.mean)),
+            ("deviation", array(&self.deviation)),
+            ("best", self.best.to_json()),
+            ("best_lines", self.best_lines.into()),
+        ]);
+        File::create(path).and_then(|mut file| writeln!(file, "{}", json))
+            .map_err(|err| format!("Couldn't write {}: {}", path, err))
+    }
+
+    /// Tries a generation of candidates and moves the search towards the best of them. Returns the average
+    /// lines cleared by each candidate, best first.
+    pub fn step(&mut self, settings: &Settings) -> Vec<(Weights, f64)> {
+        // Each generation draws from its own seed, so a resumed search carries on exactly as it would have.
+        let generation = self.generation + 1;
+        let mut rng = XorShiftRng::from_seed([generation, 0x2545f491, 0x4f6cdd1d, 0x9e3779b9]);
+        let candidates: Vec<Weights> = (0..settings.population)
+            .map(|_| {
+                let mut values = [0.0; 5];
+                for (i, value) in values.iter_mut().enumerate() {
+                    *value = Normal::new(self.mean[i], self.deviation[i]).ind_sample(&mut rng);
+                }
+                from_array(&values)
+            })
+            .collect();
+
+        let lines = evaluate(&candidates, settings);
+        let mut results: Vec<(Weights, f64)> = candidates.into_iter().zip(lines).collect();
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let elite = &results[..::std::cmp::max(1, ::std::cmp::min(settings.elite, results.len()))];
+        let noise = NOISE / generation as f64;
+        for i in 0..5 {
+            let values: Vec<f64> = elite.iter().map(|(weights, _)| to_array(weights)[i]).collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+            self.mean[i] = mean;
+            self.deviation[i] = variance.sqrt() + noise;
+        }
+        if results[0].1 > self.best_lines {
+            self.best = results[0].0;
+            self.best_lines = results[0].1;
+        }
+        self.generation = generation;
+        results
+    }
+}
+
+impl Default for Population {
+    fn default() -> Population {
+        Population::new()
+    }
+}
+
+fn to_array(weights: &Weights) -> [f64; 5] {
+    [weights.aggregate_height, weights.lines, weights.holes, weights.bumpiness, weights.wells]
+}
+
+fn from_array(values: &[f64; 5]) -> Weights {
+    Weights{
+        aggregate_height: values[0],
+        lines: values[1],
+        holes: values[2],
+        bumpiness: values[3],
+        wells: values[4],
+    }
+}
+
+/// Plays every candidate's games, spread over the threads, and returns the average lines each cleared.
+pub fn evaluate(candidates: &[Weights], settings: &Settings) -> Vec<f64> {
+    let games: Vec<(usize, u64)> = (0..candidates.len())
+        .flat_map(|candidate| (settings.seeds.0..settings.seeds.1).map(move |seed| (candidate, seed)))
+        .collect();
+    let jobs = Arc::new(Mutex::new(games.into_iter()));
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..::std::cmp::max(settings.threads, 1) {
+        let jobs = jobs.clone();
+        let tx = tx.clone();
+        let candidates = candidates.to_vec();
+        let settings = settings.clone();
+        thread::spawn(move || {
+            loop {
+                let job = jobs.lock().unwrap().next();
+                let (candidate, seed) = match job {
+                    Some(job) => job,
+                    None => break,
+                };
+                let result = sim::play(settings.rules.clone(), seed, candidates[candidate], settings.max_pieces);
+                tx.send((candidate, result.lines)).unwrap();
+            }
+        });
+    }
+    drop(tx);
+
+    let mut lines = vec![0.0; candidates.len()];
+    for (candidate, cleared) in rx {
+        lines[candidate] += cleared as f64;
+    }
+    let games = ::std::cmp::max(settings.seeds.1 - settings.seeds.0, 1) as f64;
+    lines.iter().map(|&total| total / games).collect()
+}
@@ -7,10 +7,48 @@ pub enum Color {
     Red,
     Blue,
     Orange,
+    Gray,
+    Yellow,
+    Magenta,
+    Teal,
+    White,
+    Pink,
+    Brown,
+}
+
+impl Color {
+    /// Looks up a color by its lowercase name, as used in piece set files.
+    pub fn from_name(name: &str) -> Option<Color> {
+        match name {
+            "black" => Some(Color::Black),
+            "cyan" => Some(Color::Cyan),
+            "purple" => Some(Color::Purple),
+            "green" => Some(Color::Green),
+            "red" => Some(Color::Red),
+            "blue" => Some(Color::Blue),
+            "orange" => Some(Color::Orange),
+            "gray" => Some(Color::Gray),
+            "yellow" => Some(Color::Yellow),
+            "magenta" => Some(Color::Magenta),
+            "teal" => Some(Color::Teal),
+            "white" => Some(Color::White),
+            "pink" => Some(Color::Pink),
+            "brown" => Some(Color::Brown),
+            _ => None,
+        }
+    }
 }
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum Direction {
     Left,
-    Right
+    Right,
+    /// Half a turn, in either direction.
+    Half,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
 }
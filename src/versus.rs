@@ -0,0 +1,298 @@
+use ai::{ranked_placements, Autoplayer, Placement, Planner, Weights};
+use board::*;
+use display::Display;
+use game::{Action, Game};
+use json::Json;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use rules::{Rules, FRAMES_PER_SECOND};
+use spectate::player_state;
+use std::cmp;
+use util::*;
+
+/// The number of columns of the display given to each player.
+pub const PLAYER_WIDTH: u32 = 60;
+
+/// Extra rows of garbage sent for each clear of a combo, after the Tetris Friends table, indexed by the number
+/// of clears before it in the combo.
+const COMBO_ATTACK: [u32; 12] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5];
+
+/// The rows of garbage sent for clearing every block from the board.
+const PERFECT_CLEAR_ATTACK: u32 = 10;
+
+/// One side of a versus game: a player's game, along with the garbage their opponent has sent them.
+pub struct Player {
+    pub name: String,
+    pub game: Game,
+    /// Garbage waiting to rise into the board, as the number of rows sent by each attack and the column of
+    /// their hole.
+    garbage: Vec<(u32, u32)>,
+    /// The number of pieces in a row which have cleared lines.
+    combo: u32,
+    /// The number of pieces the game had locked when it was last checked for attacks.
+    pieces: u32,
+}
+
+impl Player {
+    pub fn new(name: &str, game: Game) -> Player {
+        Player{ name: name.to_string(), game, garbage: Vec::new(), combo: 0, pieces: 0 }
+    }
+
+    /// Returns the total rows of garbage waiting to rise into the board.
+    pub fn incoming(&self) -> u32 {
+        self.garbage.iter().map(|&(lines, _)| lines).sum()
+    }
+
+    /// Queues garbage sent by the opponent, to rise into the board once a piece locks without clearing lines.
+    pub fn receive(&mut self, lines: u32, hole: u32) {
+        self.garbage.push((lines, hole));
+    }
+
+    /// Checks whether a piece has locked since the last check. A piece which cleared lines attacks, its rows of
+    /// garbage first cancelling out any garbage waiting, and the rows left over are returned to be sent to the
+    /// opponent. Otherwise the garbage waiting rises into the board.
+    pub fn check_lock(&mut self) -> u32 {
+        if self.game.pieces() == self.pieces {
+            return 0;
+        }
+        self.pieces = self.game.pieces();
+        let clear = match self.game.last_lock() {
+            Some(clear) => clear,
+            None => return 0,
+        };
+
+        if clear.lines == 0 {
+            self.combo = 0;
+            for (lines, hole) in self.garbage.drain(..) {
+                if !self.game.add_garbage(lines, hole) {
+                    break;
+                }
+            }
+            return 0;
+        }
+
+        self.combo += 1;
+        let mut attack = clear.attack() + COMBO_ATTACK[cmp::min(self.combo as usize, COMBO_ATTACK.len()) - 1];
+        if is_perfect_clear(self.game.board()) {
+            attack += PERFECT_CLEAR_ATTACK;
+        }
+        while attack > 0 && !self.garbage.is_empty() {
+            let cancelled = cmp::min(attack, self.garbage[0].0);
+            attack -= cancelled;
+            self.garbage[0].0 -= cancelled;
+            if self.garbage[0].0 == 0 {
+                self.garbage.remove(0);
+            }
+        }
+        attack
+    }
+}
+
+/// Returns true if every row of the board is either empty or full and about to be cleared.
+fn is_perfect_clear(board: &Board) -> bool {
+    board.cells.iter().all(|row| row.iter().all(|c| c.is_some()) || row.iter().all(|c| c.is_none()))
+}
+
+/// A battle as the main loop plays it, whether both players are at this keyboard or not.
+pub trait Battle {
+    /// Performs an action for a key pressed by one of the players at the keyboard, as `Game::press` does.
+    fn press(&mut self, player: usize, action: Action, repeat: bool) -> bool;
+
+    /// Tells a player's game which of their actions' keys are being held down.
+    fn set_held_actions(&mut self, player: usize, actions: Vec<Action>);
+
+    /// Runs a frame of the battle. Returns true if anything changed.
+    fn update(&mut self) -> bool;
+
+    fn render(&self, display: &mut Display);
+
+    /// Describes each player's game for spectators, as `spectate::player_state` does.
+    fn spectator_states(&self) -> Vec<Json>;
+}
+
+/// A battle between two players, each on their own board but dealt the same pieces. Clearing lines sends
+/// garbage to the opponent, and the last player left standing wins.
+pub struct Versus {
+    pub players: Vec<Player>,
+    /// Chooses the hole in each attack's garbage.
+    rng: XorShiftRng,
+    /// Plays the second player, when the computer is the opponent.
+    opponent: Option<Autoplayer>,
+}
+
+impl Versus {
+    /// Starts a battle with a random sequence of pieces.
+    pub fn new(rules: Rules) -> Versus {
+        Versus::with_seed(rules, ::rand::random())
+    }
+
+    pub fn with_seed(rules: Rules, seed: u64) -> Versus {
+        let first = Game::with_seed(rules.clone(), seed);
+        Versus{
+            players: vec![Player::new("Player 1", first), Player::new("Player 2", Game::with_seed(rules, seed))],
+            rng: XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x85ebca6b, 0xc2b2ae35]),
+            opponent: None,
+        }
+    }
+
+    /// Hands the second player over to the computer.
+    pub fn set_opponent(&mut self, opponent: Autoplayer) {
+        self.opponent = Some(opponent);
+    }
+
+    /// Returns the index of the winning player, once the other has topped out.
+    pub fn winner(&self) -> Option<usize> {
+        match (self.players[0].game.game_over(), self.players[1].game.game_over()) {
+            (None, Some(_)) => Some(0),
+            (Some(_), None) => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Returns true once either player's game has ended, which ends the battle.
+    pub fn is_over(&self) -> bool {
+        self.players.iter().any(|player| player.game.game_over().is_some())
+    }
+
+    /// Sends the garbage from any piece which has just cleared lines to the other player.
+    fn send_attacks(&mut self) {
+        for i in 0..self.players.len() {
+            let attack = self.players[i].check_lock();
+            if attack > 0 {
+                let hole = self.rng.gen_range(0, BOARD_WIDTH);
+                self.players[1 - i].receive(attack, hole);
+            }
+        }
+    }
+}
+
+impl Battle for Versus {
+    /// Undo and redo aren't allowed, as they would take back garbage that has been sent.
+    fn press(&mut self, player: usize, action: Action, repeat: bool) -> bool {
+        if self.is_over() || action == Action::Undo || action == Action::Redo {
+            return false;
+        }
+        let changed = self.players[player].game.press(action, repeat);
+        self.send_attacks();
+        changed
+    }
+
+    fn set_held_actions(&mut self, player: usize, actions: Vec<Action>) {
+        self.players[player].game.set_held_actions(actions);
+    }
+
+    /// Runs a frame of both games, and of the computer opponent if there is one.
+    fn update(&mut self) -> bool {
+        if self.is_over() {
+            return false;
+        }
+        let mut changed = false;
+        for player in &mut self.players {
+            changed = player.game.update() || changed;
+        }
+        if let Some(ref mut opponent) = self.opponent {
+            if let Some(action) = opponent.update(&self.players[1].game) {
+                changed = self.players[1].game.perform(action) || changed;
+            }
+        }
+        self.send_attacks();
+        changed
+    }
+
+    /// Draws both games side by side, with the garbage waiting for each player and the winner.
+    fn render(&self, display: &mut Display) {
+        for (i, player) in self.players.iter().enumerate() {
+            display.set_origin(i as u32 * PLAYER_WIDTH);
+            player.game.render(display);
+            let status = if self.winner() == Some(i) { Some("Winner!") } else { None };
+            render_status(display, &player.name, player.incoming(), status);
+        }
+        display.set_origin(0);
+    }
+
+    fn spectator_states(&self) -> Vec<Json> {
+        self.players.iter().map(|player| player_state(&player.name, &player.game, player.incoming())).collect()
+    }
+}
+
+/// Draws a player's name and the garbage waiting for them beside their board, along with a status such as
+/// whether they have won.
+pub fn render_status(display: &mut Display, name: &str, incoming: u32, status: Option<&str>) {
+    let left_margin = BOARD_WIDTH * 2 + 5;
+    display.set_text(name, left_margin, 1, Color::Red, Color::Black);
+    if incoming > 0 {
+        display.set_text(format!("Incoming garbage: {}", incoming), left_margin, 16, Color::Red, Color::Black);
+    }
+    if let Some(status) = status {
+        display.set_text(status, left_margin, 20, Color::Red, Color::Black);
+    }
+}
+
+/// How strongly the computer plays as an opponent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Difficulty {
+    /// The most pieces it places a second.
+    pub pieces_per_second: f64,
+    /// The chance, from 0 to 1, that it makes a mistake with a piece, putting it somewhere other than the
+    /// best place it found.
+    pub mistakes: f64,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        let (pieces_per_second, mistakes) = match name {
+            "easy" => (0.5, 0.3),
+            "medium" => (1.0, 0.15),
+            "hard" => (2.0, 0.05),
+            "expert" => (4.0, 0.0),
+            _ => return None,
+        };
+        Some(Difficulty{ pieces_per_second, mistakes })
+    }
+}
+
+/// The number of next best placements a mistake is chosen from.
+const MISTAKE_CHOICES: usize = 4;
+
+/// Plans placements for a computer opponent, playing like the AI but held back by its difficulty: it waits
+/// before planning each piece so as not to play faster than its limit, and sometimes picks a worse placement
+/// than the best.
+pub struct Opponent {
+    difficulty: Difficulty,
+    weights: Weights,
+    rng: XorShiftRng,
+    /// The frame on which the last placement was planned.
+    last_plan: Option<u32>,
+}
+
+impl Opponent {
+    pub fn new(difficulty: Difficulty, weights: Weights) -> Opponent {
+        Opponent{
+            difficulty,
+            weights,
+            rng: XorShiftRng::from_seed(::rand::random()),
+            last_plan: None,
+        }
+    }
+}
+
+impl Planner for Opponent {
+    fn plan(&mut self, game: &Game) -> Option<Placement> {
+        let (piece, position) = game.current_piece()?;
+        let interval = (FRAMES_PER_SECOND as f64 / self.difficulty.pieces_per_second) as u32;
+        if self.last_plan.is_some_and(|frame| game.frames() < frame + interval) {
+            return None;
+        }
+        self.last_plan = Some(game.frames());
+
+        let next = game.next_piece();
+        let ranked = ranked_placements(game.board(), piece, position, Some(&next), game.movement(), &self.weights);
+        let choice = if ranked.len() > 1 && self.rng.gen::<f64>() < self.difficulty.mistakes {
+            self.rng.gen_range(1, cmp::min(ranked.len(), MISTAKE_CHOICES + 1))
+        } else {
+            0
+        };
+        ranked.into_iter().nth(choice).or_else(|| {
+            Some(Placement{ piece: piece.clone(), position: game.find_dropped_position(), hold: false })
+        })
+    }
+}